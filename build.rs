@@ -0,0 +1,20 @@
+fn main() {
+  #[cfg(feature = "capi")]
+  {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    // Build scripts may only write to OUT_DIR. The checked-in copy under include/ is kept up to date by
+    // tests/capi.rs.
+    cbindgen::Builder::new()
+      .with_src(format!("{}/src/capi.rs", crate_dir))
+      .with_src(format!("{}/src/grapple/lasercan.rs", crate_dir))
+      .with_src(format!("{}/src/grapple/mitocandria.rs", crate_dir))
+      .with_config(cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap())
+      .generate()
+      .expect("Unable to generate C header")
+      .write_to_file(format!("{}/grapple_frc_msgs.h", out_dir));
+  }
+}
@@ -0,0 +1,30 @@
+#![no_main]
+
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal}, grapple::{fragments::FragmentReassembler, MANUFACTURER_GRAPPLE}, ManufacturerMessage, MessageId};
+use libfuzzer_sys::fuzz_target;
+
+// Input is a sequence of frames, each a little-endian 29-bit ID, a time delta, a length and the frame data.
+// The manufacturer is forced to Grapple so every frame reaches the reassembler.
+fuzz_target!(|data: &[u8]| {
+  let (mut rx, mut tx) = FragmentReassembler::new(100, 8).split();
+  let mut now = 0i64;
+  let mut rest = data;
+
+  while rest.len() >= 6 {
+    let mut id = MessageId::from(u32::from_le_bytes(rest[0..4].try_into().unwrap()));
+    id.manufacturer = MANUFACTURER_GRAPPLE;
+    now += rest[4] as i64;
+    let len = (rest[5] as usize % 65).min(rest.len() - 6);
+    let (frame, remaining) = rest[6..].split_at(len);
+    rest = remaining;
+
+    if let Ok(ManufacturerMessage::Grapple(msg)) = ManufacturerMessage::read(&mut BitView::new(frame), id) {
+      let mut storage = vec![];
+      if let Ok(Some((gid, msg))) = rx.defragment(now, &id, msg, &mut storage) {
+        let _ = format!("{:?}", msg);
+        let _ = tx.maybe_fragment(gid.device_id, msg.to_static(), &mut |_, _| {});
+      }
+    }
+  }
+});
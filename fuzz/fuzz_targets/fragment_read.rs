@@ -0,0 +1,17 @@
+#![no_main]
+
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal, Marshal, VecBitWriter}, grapple::{fragments::Fragment, GrappleMessageId}, MessageId};
+use libfuzzer_sys::fuzz_target;
+
+// Input is a little-endian 29-bit ID followed by the frame data
+fuzz_target!(|data: &[u8]| {
+  if data.len() < 4 {
+    return;
+  }
+
+  let id = GrappleMessageId::from(MessageId::from(u32::from_le_bytes(data[0..4].try_into().unwrap())));
+  if let Ok(frag) = Fragment::read(&mut BitView::new(&data[4..]), id.clone()) {
+    let mut writer = VecBitWriter::new();
+    let _ = frag.write(&mut writer, id);
+  }
+});
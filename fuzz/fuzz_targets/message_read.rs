@@ -0,0 +1,33 @@
+#![no_main]
+
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{binmarshal::{BitView, Demarshal, Marshal, VecBitWriter}, transport::MessageDecoder, Message, MessageId, Validate, ValidateUtf8};
+use libfuzzer_sys::fuzz_target;
+
+// Input is a little-endian 29-bit ID followed by the frame data
+fuzz_target!(|data: &[u8]| {
+  if data.len() < 4 {
+    return;
+  }
+
+  // As bridged, with the ID inline. Strings aren't checked by Demarshal, so they have to be checked here.
+  if let Ok(msg) = Message::read(&mut BitView::new(data), ()) {
+    if msg.validate_utf8().is_ok() {
+      exercise(msg);
+    }
+  }
+
+  let id = MessageId::from(u32::from_le_bytes(data[0..4].try_into().unwrap()));
+  if let Ok(msg) = MessageDecoder::decode_raw(id, &data[4..]) {
+    exercise(msg);
+  }
+});
+
+// Anything that decodes must be safe to validate, print, and encode again
+fn exercise(msg: Message) {
+  let _ = msg.validate();
+  let _ = format!("{:?}", msg);
+
+  let mut writer = VecBitWriter::new();
+  let _ = msg.to_static().write(&mut writer, ());
+}
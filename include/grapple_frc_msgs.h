@@ -0,0 +1,256 @@
+#ifndef GRAPPLE_FRC_MSGS_H
+#define GRAPPLE_FRC_MSGS_H
+
+/* Generated by cbindgen from src/capi.rs - do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+enum GrplStatus
+#ifdef __cplusplus
+  : int32_t
+#endif // __cplusplus
+ {
+  GrplStatus_Ok = 0,
+  GrplStatus_NullPointer = 1,
+  GrplStatus_BufferTooSmall = 2,
+  GrplStatus_IllegalValue = 3,
+  GrplStatus_IllegalTag = 4,
+  GrplStatus_CoercionError = 5,
+  GrplStatus_ExpectedSentinel = 6,
+  GrplStatus_InvalidJson = 7,
+  GrplStatus_WrongMessageType = 8,
+  GrplStatus_Discontiguous = 9,
+  GrplStatus_CrcMismatch = 10,
+  GrplStatus_FragmentIdsExhausted = 11,
+};
+#ifndef __cplusplus
+typedef int32_t GrplStatus;
+#endif // __cplusplus
+
+enum LaserCanRangingMode
+#ifdef __cplusplus
+  : uint8_t
+#endif // __cplusplus
+ {
+  LaserCanRangingMode_Short,
+  LaserCanRangingMode_Long,
+};
+#ifndef __cplusplus
+typedef uint8_t LaserCanRangingMode;
+#endif // __cplusplus
+
+enum LaserCanTimingBudget
+#ifdef __cplusplus
+  : uint8_t
+#endif // __cplusplus
+ {
+  LaserCanTimingBudget_TB20ms = 20,
+  LaserCanTimingBudget_TB33ms = 33,
+  LaserCanTimingBudget_TB50ms = 50,
+  LaserCanTimingBudget_TB100ms = 100,
+};
+#ifndef __cplusplus
+typedef uint8_t LaserCanTimingBudget;
+#endif // __cplusplus
+
+typedef struct GrplMessage GrplMessage;
+
+typedef struct GrplReassembler GrplReassembler;
+
+typedef uint8_t LaserCanRoiU4;
+
+typedef struct LaserCanRoi {
+  LaserCanRoiU4 x;
+  LaserCanRoiU4 y;
+  LaserCanRoiU4 w;
+  LaserCanRoiU4 h;
+} LaserCanRoi;
+
+typedef struct LaserCanMeasurement {
+  uint8_t status;
+  uint16_t distance_mm;
+  uint16_t ambient;
+  LaserCanRangingMode mode;
+  LaserCanTimingBudget budget;
+  struct LaserCanRoi roi;
+} LaserCanMeasurement;
+
+typedef enum MitocandriaChannelStatus_Tag {
+  MitocandriaChannelStatus_Switchable,
+  MitocandriaChannelStatus_NonSwitchable,
+  MitocandriaChannelStatus_Adjustable,
+} MitocandriaChannelStatus_Tag;
+
+typedef struct MitocandriaChannelStatus_Switchable_Body {
+  bool enabled;
+  uint16_t current;
+} MitocandriaChannelStatus_Switchable_Body;
+
+typedef struct MitocandriaChannelStatus_NonSwitchable_Body {
+  uint16_t current;
+} MitocandriaChannelStatus_NonSwitchable_Body;
+
+typedef struct MitocandriaChannelStatus_Adjustable_Body {
+  bool enabled;
+  uint16_t voltage;
+  uint16_t voltage_setpoint;
+  uint16_t current;
+} MitocandriaChannelStatus_Adjustable_Body;
+
+typedef struct MitocandriaChannelStatus {
+  MitocandriaChannelStatus_Tag tag;
+  union {
+    MitocandriaChannelStatus_Switchable_Body switchable;
+    MitocandriaChannelStatus_NonSwitchable_Body non_switchable;
+    MitocandriaChannelStatus_Adjustable_Body adjustable;
+  };
+} MitocandriaChannelStatus;
+
+typedef struct MitocandriaStatusFrame {
+  struct MitocandriaChannelStatus channels[5];
+} MitocandriaStatusFrame;
+
+typedef void (*GrplFrameCallback)(void *user, uint32_t id, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Decode a single frame. Fragments are returned as-is, use a reassembler to put them back together.
+ *
+ * # Safety
+ * `data` must point to `len` readable bytes and `out` must be a valid pointer.
+ */
+GrplStatus grpl_message_decode(uint32_t id,
+                               const uint8_t *data,
+                               size_t len,
+                               struct GrplMessage **out);
+
+/**
+ * # Safety
+ * `json` must be a valid, NUL-terminated string and `out` must be a valid pointer.
+ */
+GrplStatus grpl_message_from_json(const char *json, struct GrplMessage **out);
+
+/**
+ * Write the message as NUL-terminated JSON into `buf`. `len_out` is set to the required length (excluding the
+ * terminator) even if the buffer is too small.
+ *
+ * # Safety
+ * `msg` must be a valid handle, `buf` must point to `buf_len` writable bytes.
+ */
+GrplStatus grpl_message_to_json(const struct GrplMessage *msg,
+                                char *buf,
+                                size_t buf_len,
+                                size_t *len_out);
+
+/**
+ * # Safety
+ * `msg` must be a handle returned by this library, or NULL.
+ */
+void grpl_message_free(struct GrplMessage *msg);
+
+/**
+ * The message's 29-bit CAN ID, or 0 if `msg` is NULL.
+ *
+ * # Safety
+ * `msg` must be a valid handle, or NULL.
+ */
+uint32_t grpl_message_id(const struct GrplMessage *msg);
+
+/**
+ * Encode as a single, unfragmented frame.
+ *
+ * # Safety
+ * `msg` must be a valid handle, `buf` must point to `buf_len` writable bytes and `id_out` and `len_out` must be
+ * valid pointers.
+ */
+GrplStatus grpl_message_encode(const struct GrplMessage *msg,
+                               uint32_t *id_out,
+                               uint8_t *buf,
+                               size_t buf_len,
+                               size_t *len_out);
+
+/**
+ * # Safety
+ * `msg` must be a valid handle and `out` must be a valid pointer.
+ */
+GrplStatus grpl_message_as_lasercan_measurement(const struct GrplMessage *msg,
+                                                struct LaserCanMeasurement *out);
+
+/**
+ * # Safety
+ * `msg` must be a valid handle and `out` must be a valid pointer.
+ */
+GrplStatus grpl_message_as_mitocandria_status(const struct GrplMessage *msg,
+                                              struct MitocandriaStatusFrame *out);
+
+struct GrplReassembler *grpl_reassembler_new(int64_t age_off, size_t max_fragment_size);
+
+/**
+ * # Safety
+ * `reassembler` must be a handle returned by grpl_reassembler_new, or NULL.
+ */
+void grpl_reassembler_free(struct GrplReassembler *reassembler);
+
+/**
+ * Feed a received frame. `out` is set to the decoded message once complete, or NULL if more fragments are needed.
+ *
+ * # Safety
+ * `reassembler` must be a valid handle, `data` must point to `len` readable bytes and `out` must be a valid pointer.
+ */
+GrplStatus grpl_reassembler_defragment(struct GrplReassembler *reassembler,
+                                       int64_t now,
+                                       uint32_t id,
+                                       const uint8_t *data,
+                                       size_t len,
+                                       struct GrplMessage **out);
+
+/**
+ * Encode a message into frames, fragmenting if required. `callback` is called once per frame, in order.
+ *
+ * # Safety
+ * `reassembler` and `msg` must be valid handles.
+ */
+GrplStatus grpl_reassembler_fragment(struct GrplReassembler *reassembler,
+                                     const struct GrplMessage *msg,
+                                     GrplFrameCallback callback,
+                                     void *user);
+
+/**
+ * `mode` is 0 for short range, 1 for long range. Returns NULL if out of range.
+ */
+struct GrplMessage *grpl_lasercan_set_range(uint8_t device_id, uint8_t mode);
+
+/**
+ * `budget_ms` must be one of 20, 33, 50 or 100. Returns NULL otherwise.
+ */
+struct GrplMessage *grpl_lasercan_set_timing_budget(uint8_t device_id, uint8_t budget_ms);
+
+/**
+ * Returns NULL if the ROI is invalid.
+ */
+struct GrplMessage *grpl_lasercan_set_roi(uint8_t device_id,
+                                          uint8_t x,
+                                          uint8_t y,
+                                          uint8_t w,
+                                          uint8_t h);
+
+struct GrplMessage *grpl_mitocandria_set_switchable_channel(uint8_t device_id,
+                                                            uint8_t channel,
+                                                            bool enabled);
+
+struct GrplMessage *grpl_mitocandria_set_adjustable_channel(uint8_t device_id,
+                                                            uint8_t channel,
+                                                            uint16_t voltage);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* GRAPPLE_FRC_MSGS_H */
@@ -0,0 +1,69 @@
+use std::io::BufRead;
+
+use anyhow::{anyhow, bail};
+use grapple_frc_msgs::{Message, MessageId, capture::candump, transport::{CanFrame, MessageDecoder}};
+
+use crate::describe::describe;
+
+fn parse_id(s: &str) -> anyhow::Result<MessageId> {
+  let s = s.trim();
+  let s = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")).unwrap_or(s);
+  Ok(MessageId::from(u32::from_str_radix(s, 16).map_err(|_| anyhow!("Invalid CAN ID: {}", s))?))
+}
+
+fn parse_payload(s: &str) -> anyhow::Result<Vec<u8>> {
+  let s: String = s.chars().filter(|c| !c.is_whitespace() && *c != ':' && *c != '.').collect();
+  if !s.len().is_multiple_of(2) {
+    bail!("Payload must have an even number of hex digits: {}", s);
+  }
+  candump::parse_hex(&s).map_err(|_| anyhow!("Invalid payload: {}", s))
+}
+
+// Accepts `ID HEX`, `ID#HEX` or a full candump log line.
+pub fn parse_frame(line: &str) -> anyhow::Result<CanFrame> {
+  let line = line.trim();
+  if line.starts_with('(') {
+    return Ok(candump::parse_line(line)?.frame);
+  }
+
+  let (id, data) = line.split_once('#')
+    .or_else(|| line.split_once(char::is_whitespace))
+    .unwrap_or((line, ""));
+  Ok(CanFrame::new(parse_id(id)?, 0, &parse_payload(data)?))
+}
+
+fn print(msg: &Message, json: bool, pretty: bool) -> anyhow::Result<()> {
+  match (json, pretty) {
+    (true, true) => println!("{}", serde_json::to_string_pretty(msg)?),
+    (true, false) => println!("{}", serde_json::to_string(msg)?),
+    (false, _) => println!("{}", describe(msg)),
+  }
+  Ok(())
+}
+
+pub fn decode_one(id: &str, payload: &str, json: bool) -> anyhow::Result<()> {
+  let frame = CanFrame::new(parse_id(id)?, 0, &parse_payload(payload)?);
+  let msg = MessageDecoder::decode_raw(frame.id, &frame.data[..]).map_err(|e| anyhow!("Could not decode frame: {:?}", e))?;
+  print(&msg, json, true)
+}
+
+// Decode a stream of frames from stdin, reassembling fragments. Errors are reported and skipped.
+pub fn decode_stream(json: bool) -> anyhow::Result<()> {
+  let mut decoder = MessageDecoder::new(i64::MAX);
+
+  for line in std::io::stdin().lock().lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let result = parse_frame(&line).and_then(|frame| decoder.decode(&frame).map_err(|e| anyhow!("Could not decode frame: {:?}", e)));
+    match result {
+      Ok(Some(msg)) => print(&msg, json, false)?,
+      Ok(None) => (),
+      Err(e) => eprintln!("error: {}: {}", line.trim(), e),
+    }
+  }
+
+  Ok(())
+}
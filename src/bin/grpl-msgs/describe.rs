@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+
+use grapple_frc_msgs::{
+  DEVICE_TYPE_BROADCAST, DEVICE_TYPE_FIRMWARE_UPGRADE, ManufacturerMessage, Message,
+  grapple::{DEVICE_TYPE_DISTANCE_SENSOR, DEVICE_TYPE_IO_BREAKOUT, DEVICE_TYPE_MISC_WIRE, DEVICE_TYPE_POWER_DISTRIBUTION_MODULE, GrappleDeviceMessage, MaybeFragment},
+};
+use serde_json::Value;
+
+pub fn device_name(msg: &Message) -> &'static str {
+  match &msg.msg {
+    #[cfg(feature = "ni")]
+    ManufacturerMessage::Ni(_) => "NI",
+    ManufacturerMessage::Grapple(MaybeFragment::Fragment(_)) => "Fragment",
+    ManufacturerMessage::Grapple(MaybeFragment::Message(m)) => match m {
+      GrappleDeviceMessage::Broadcast(_) => "Broadcast",
+      GrappleDeviceMessage::FirmwareUpdate(_) => "Firmware Update",
+      #[cfg(feature = "grapple_lasercan")]
+      GrappleDeviceMessage::DistanceSensor(_) => "LaserCAN",
+      #[cfg(feature = "grapple_mitocandria")]
+      GrappleDeviceMessage::PowerDistributionModule(_) => "MitoCANdria",
+      #[cfg(feature = "grapple_flexican")]
+      GrappleDeviceMessage::IOBreakout(_) => "FlexiCAN",
+      GrappleDeviceMessage::Misc(_) => "Misc",
+    }
+  }
+}
+
+// Name of a Grapple device type, for when we only have the CAN ID
+pub fn device_type_name(device_type: u8) -> Cow<'static, str> {
+  match device_type {
+    DEVICE_TYPE_BROADCAST => "Broadcast".into(),
+    DEVICE_TYPE_FIRMWARE_UPGRADE => "Firmware Update".into(),
+    DEVICE_TYPE_DISTANCE_SENSOR => "LaserCAN".into(),
+    DEVICE_TYPE_POWER_DISTRIBUTION_MODULE => "MitoCANdria".into(),
+    DEVICE_TYPE_IO_BREAKOUT => "FlexiCAN".into(),
+    DEVICE_TYPE_MISC_WIRE => "Misc".into(),
+    other => format!("Type {}", other).into(),
+  }
+}
+
+// The part of the message below the device type, e.g. {"type": "SetRoi", "data": ...} for a LaserCAN
+pub fn body(msg: &Message) -> Value {
+  let value = match &msg.msg {
+    #[cfg(feature = "ni")]
+    ManufacturerMessage::Ni(ni) => serde_json::to_value(ni),
+    ManufacturerMessage::Grapple(MaybeFragment::Fragment(f)) => serde_json::to_value(f),
+    ManufacturerMessage::Grapple(MaybeFragment::Message(m)) => serde_json::to_value(m).map(|mut v| v["data"].take()),
+  };
+  value.unwrap_or(Value::Null)
+}
+
+// Chain of variant names through the message, e.g. "SetRoi::Request", and the innermost payload
+pub fn variant(value: &Value) -> (String, &Value) {
+  let mut names = vec![];
+  let mut v = value;
+  while let Some(ty) = v.get("type").and_then(|t| t.as_str()) {
+    names.push(ty);
+    v = &v["data"];
+  }
+  (names.join("::"), v)
+}
+
+pub fn render(value: &Value) -> String {
+  match value {
+    Value::Null => "()".to_owned(),
+    Value::Bool(b) => b.to_string(),
+    Value::Number(n) => n.to_string(),
+    Value::String(s) => format!("{:?}", s),
+    Value::Array(a) => format!("[{}]", a.iter().map(render).collect::<Vec<_>>().join(", ")),
+    Value::Object(o) => match (o.get("type").and_then(|t| t.as_str()), o.get("data")) {
+      (Some(ty), Some(Value::Null) | None) if o.len() <= 2 => ty.to_owned(),
+      (Some(ty), Some(data)) if o.len() == 2 => format!("{} {}", ty, render(data)),
+      _ => match o.iter().next() {
+        // Externally tagged enums, e.g. {"Ok": null}
+        Some((k, v)) if o.len() == 1 && k.starts_with(|c: char| c.is_ascii_uppercase()) => match v {
+          Value::Null => k.clone(),
+          v => format!("{} {}", k, render(v)),
+        },
+        _ => format!("{{{}}}", o.iter().map(|(k, v)| format!("{}: {}", k, render(v))).collect::<Vec<_>>().join(", ")),
+      }
+    }
+  }
+}
+
+pub fn describe(msg: &Message) -> String {
+  format!("{} #{} {}", device_name(msg), msg.id.device_id, render(&body(msg)))
+}
@@ -0,0 +1,66 @@
+use clap::{Parser, Subcommand};
+
+use std::{fs::File, io::BufReader};
+
+use grapple_frc_msgs::capture::candump::CandumpReader;
+
+mod decode;
+mod describe;
+mod monitor;
+
+#[derive(Parser)]
+#[command(name = "grpl-msgs", version, about = "Decode and inspect FRC CAN traffic")]
+struct Cli {
+  #[command(subcommand)]
+  command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Decode a single frame, or a stream of frames on stdin if no ID is given
+  Decode {
+    /// 29-bit CAN ID, in hex
+    id: Option<String>,
+    /// Frame payload, in hex
+    payload: Option<String>,
+    /// Print as JSON instead of text
+    #[arg(short, long)]
+    json: bool,
+  },
+  /// Show a live table of the latest message for each CAN ID
+  Monitor {
+    /// SocketCAN interface to listen on, e.g. can0
+    #[cfg(all(feature = "socketcan", target_os = "linux"))]
+    #[arg(long, conflicts_with = "candump")]
+    socketcan: Option<String>,
+    /// Open the SocketCAN interface in CAN FD mode
+    #[cfg(all(feature = "socketcan", target_os = "linux"))]
+    #[arg(long, requires = "socketcan")]
+    fd: bool,
+    /// candump log file to replay
+    #[arg(long)]
+    candump: Option<String>,
+    /// Replay the log at its original speed instead of printing a summary
+    #[arg(long, requires = "candump")]
+    realtime: bool,
+  },
+}
+
+fn main() -> anyhow::Result<()> {
+  let cli = Cli::parse();
+
+  match cli.command {
+    Command::Decode { id: Some(id), payload, json } => decode::decode_one(&id, payload.as_deref().unwrap_or(""), json),
+    Command::Decode { id: None, json, .. } => decode::decode_stream(json),
+    #[cfg(all(feature = "socketcan", target_os = "linux"))]
+    Command::Monitor { socketcan: Some(iface), fd, .. } => {
+      use grapple_frc_msgs::{can::CanFrameFormat, transport::socketcan::SocketCanTransport};
+      let format = if fd { CanFrameFormat::Fd } else { CanFrameFormat::Classic };
+      monitor::run(SocketCanTransport::open_with_format(&iface, format)?, true, true)
+    },
+    Command::Monitor { candump: Some(path), realtime, .. } => {
+      monitor::run(CandumpReader::new(BufReader::new(File::open(path)?), i64::MAX), realtime, false)
+    },
+    Command::Monitor { .. } => anyhow::bail!("No source given, use --candump or --socketcan"),
+  }
+}
@@ -0,0 +1,179 @@
+use std::{collections::BTreeMap, collections::VecDeque, fmt::Debug, io::Write, time::{Duration, Instant}};
+
+use anyhow::anyhow;
+use grapple_frc_msgs::{ManufacturerMessage, Message, grapple::{MaybeFragment, fragments::FragmentReassemblerRx}, transport::{CanTransport, MessageDecoder}};
+#[cfg(feature = "ni")]
+use grapple_frc_msgs::ni::{NiDeviceMessage, NiRobotControllerMessage, NiRioHeartbeat};
+
+use crate::describe::{body, device_name, device_type_name, render, variant};
+
+const RATE_WINDOW_US: i64 = 1_000_000;
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const FRAGMENT_AGE_OFF_US: i64 = 500_000;
+
+struct Row {
+  device: &'static str,
+  device_id: u8,
+  variant: String,
+  latest: String,
+  last_seen: i64,
+  seen: VecDeque<i64>,
+}
+
+#[derive(Default)]
+struct Monitor {
+  rows: BTreeMap<u32, Row>,
+  frames: VecDeque<i64>,
+  fragments: VecDeque<i64>,
+  decode_errors: usize,
+  heartbeat: Option<String>,
+}
+
+impl Monitor {
+  fn on_frame(&mut self, now: i64, is_fragment: bool) {
+    self.frames.push_back(now);
+    if is_fragment {
+      self.fragments.push_back(now);
+    }
+  }
+
+  fn on_message(&mut self, now: i64, msg: &Message) {
+    #[cfg(feature = "ni")]
+    if let ManufacturerMessage::Ni(NiDeviceMessage::RobotController(NiRobotControllerMessage::Heartbeat(NiRioHeartbeat::Hearbeat(hb)))) = &msg.msg {
+      let mode = if hb.test { "Test" } else if hb.autonomous { "Auto" } else { "Teleop" };
+      self.heartbeat = Some(format!(
+        "roboRIO: {} {} {} Alliance{}",
+        if hb.enabled { "Enabled" } else { "Disabled" }, mode,
+        if hb.red_alliance { "Red" } else { "Blue" },
+        if hb.watchdog_enabled { "" } else { " (Watchdog Tripped)" }
+      ));
+    }
+
+    let body = body(msg);
+    let (variant, payload) = variant(&body);
+    let row = self.rows.entry(msg.id.into()).or_insert_with(|| Row {
+      device: device_name(msg),
+      device_id: msg.id.device_id,
+      variant: String::new(),
+      latest: String::new(),
+      last_seen: now,
+      seen: VecDeque::new(),
+    });
+    row.variant = variant;
+    row.latest = render(payload);
+    row.last_seen = now;
+    row.seen.push_back(now);
+  }
+
+  fn age_off(&mut self, now: i64) {
+    let cutoff = now - RATE_WINDOW_US;
+    for q in self.rows.values_mut().map(|r| &mut r.seen).chain([&mut self.frames, &mut self.fragments]) {
+      while q.front().is_some_and(|&t| t < cutoff) {
+        q.pop_front();
+      }
+    }
+  }
+
+  fn render<W: Write>(&self, out: &mut W, now: i64, rx: &FragmentReassemblerRx) -> std::io::Result<()> {
+    writeln!(
+      out, "{} frames/s ({} fragments/s), {} decode errors",
+      self.frames.len(), self.fragments.len(), self.decode_errors
+    )?;
+    let stats = rx.stats();
+    writeln!(
+      out, "Fragmented messages: {} in flight, {} completed, {} failed, {} aged off, {} abandoned, {} duplicate fragments",
+      stats.in_flight, stats.completed, stats.decode_failures, stats.aged_off, stats.abandoned, stats.duplicates
+    )?;
+    if let Some(hb) = &self.heartbeat {
+      writeln!(out, "{}", hb)?;
+    }
+    writeln!(out)?;
+    writeln!(out, "{:<10} {:<16} {:>3} {:<36} {:>6} {:>8}  Latest", "ID", "Device", "#", "Message", "Hz", "Age (ms)")?;
+    for (id, row) in &self.rows {
+      let mut latest = row.latest.clone();
+      if latest.len() > 96 {
+        latest.truncate(latest.char_indices().nth(93).map(|x| x.0).unwrap_or(latest.len()));
+        latest.push_str("...");
+      }
+      writeln!(
+        out, "{:08X}   {:<16} {:>3} {:<36} {:>6} {:>8}  {}",
+        id, row.device, row.device_id, row.variant, row.seen.len(), (now - row.last_seen) / 1000, latest
+      )?;
+    }
+
+    let devices = rx.device_stats();
+    if !devices.is_empty() {
+      writeln!(out)?;
+      writeln!(out, "{:<16} {:>3} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>10}", "Device", "#", "In Flight", "Started", "Completed", "Failed", "Aged Off", "Abandoned", "Duplicates")?;
+      for (key, s) in &devices {
+        writeln!(
+          out, "{:<16} {:>3} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>10}",
+          device_type_name(key.device_type), key.device_id, s.in_flight, s.started, s.completed, s.decode_failures, s.aged_off, s.abandoned, s.duplicates
+        )?;
+      }
+    }
+    Ok(())
+  }
+}
+
+// Watch a transport, refreshing the table in place. If `realtime` is false (e.g. replaying a log as fast as
+// possible), the table is only printed once the transport runs dry.
+pub fn run<T: CanTransport>(mut transport: T, realtime: bool, live: bool) -> anyhow::Result<()>
+where
+  T::Error: Debug
+{
+  let mut monitor = Monitor::default();
+  let mut decoder = MessageDecoder::new(FRAGMENT_AGE_OFF_US);
+  let mut last_refresh = Instant::now();
+  let start = Instant::now();
+  let mut log_start = None;
+
+  loop {
+    match transport.recv().map_err(|e| anyhow!("{:?}", e))? {
+      Some(frame) => {
+        // Pace replayed logs to their original timing
+        if realtime && !live {
+          let log_start = *log_start.get_or_insert(frame.timestamp);
+          let due = Duration::from_micros((frame.timestamp - log_start).max(0) as u64);
+          if let Some(wait) = due.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+          }
+        }
+
+        match MessageDecoder::decode_raw(frame.id, &frame.data[..]) {
+          Ok(msg) => {
+            monitor.on_frame(frame.timestamp, matches!(msg.msg, ManufacturerMessage::Grapple(MaybeFragment::Fragment(_))));
+            match decoder.reassemble(frame.timestamp, msg) {
+              Ok(Some(msg)) => monitor.on_message(frame.timestamp, &msg),
+              Ok(None) => (),
+              Err(_) => monitor.decode_errors += 1,
+            }
+          },
+          Err(_) => {
+            monitor.on_frame(frame.timestamp, false);
+            monitor.decode_errors += 1;
+          },
+        }
+      },
+      None if live => std::thread::sleep(Duration::from_millis(1)),
+      None => break,
+    }
+
+    if realtime && last_refresh.elapsed() >= REFRESH_INTERVAL {
+      last_refresh = Instant::now();
+      let now = transport.now();
+      monitor.age_off(now);
+      decoder.reassembler_mut().prune(now);
+      let mut out = std::io::stdout().lock();
+      write!(out, "\x1b[2J\x1b[H")?;
+      monitor.render(&mut out, now, decoder.reassembler())?;
+      out.flush()?;
+    }
+  }
+
+  let now = transport.now();
+  monitor.age_off(now);
+  decoder.reassembler_mut().prune(now);
+  monitor.render(&mut std::io::stdout().lock(), now, decoder.reassembler())?;
+  Ok(())
+}
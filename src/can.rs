@@ -0,0 +1,43 @@
+pub const CAN_MAX_DATA_LEN: usize = 8;
+pub const CANFD_MAX_DATA_LEN: usize = 64;
+
+// Data lengths addressable by each DLC value. CAN 2.0 stops at DLC 8, CAN FD extends the
+// remaining codes to 12-64 byte steps.
+pub const CANFD_DLC_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum CanFrameFormat {
+  #[default]
+  Classic,
+  Fd
+}
+
+impl CanFrameFormat {
+  pub fn max_data_len(&self) -> usize {
+    match self {
+      CanFrameFormat::Classic => CAN_MAX_DATA_LEN,
+      CanFrameFormat::Fd => CANFD_MAX_DATA_LEN,
+    }
+  }
+
+  // Smallest frame length that can carry `len` bytes on this bus, or None if it won't fit.
+  pub fn padded_len(&self, len: usize) -> Option<usize> {
+    if len > self.max_data_len() {
+      return None;
+    }
+    CANFD_DLC_LENGTHS.iter().copied().find(|&l| l >= len)
+  }
+
+  pub fn dlc_for_len(&self, len: usize) -> Option<u8> {
+    self.padded_len(len).and_then(|l| CANFD_DLC_LENGTHS.iter().position(|&x| x == l)).map(|x| x as u8)
+  }
+
+  pub fn len_for_dlc(&self, dlc: u8) -> usize {
+    match self {
+      CanFrameFormat::Classic => (dlc as usize).min(CAN_MAX_DATA_LEN),
+      CanFrameFormat::Fd => CANFD_DLC_LENGTHS[(dlc & 0b1111) as usize],
+    }
+  }
+}
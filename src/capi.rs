@@ -0,0 +1,367 @@
+use core::{ffi::c_char, ptr};
+
+use binmarshal::{BitWriter, Marshal, MarshalError, MarshalUpdate, VecBitWriter};
+use bounded_static::IntoBoundedStatic;
+
+use crate::{ManufacturerMessage, Message, grapple::{GrappleDeviceMessage, MaybeFragment, fragments::{DefragmentError, FragmentError, FragmentReassembler, FragmentReassemblerTx}}, transport::{CanFrame, MessageDecoder, encode_message}};
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrplStatus {
+  Ok = 0,
+  NullPointer = 1,
+  BufferTooSmall = 2,
+  IllegalValue = 3,
+  IllegalTag = 4,
+  CoercionError = 5,
+  ExpectedSentinel = 6,
+  InvalidJson = 7,
+  WrongMessageType = 8,
+  Discontiguous = 9,
+  CrcMismatch = 10,
+  FragmentIdsExhausted = 11,
+}
+
+impl From<MarshalError> for GrplStatus {
+  fn from(value: MarshalError) -> Self {
+    match value {
+      MarshalError::BufferTooSmall => GrplStatus::BufferTooSmall,
+      MarshalError::IllegalValue { .. } => GrplStatus::IllegalValue,
+      MarshalError::IllegalTag => GrplStatus::IllegalTag,
+      MarshalError::CoercionError => GrplStatus::CoercionError,
+      MarshalError::ExpectedSentinel => GrplStatus::ExpectedSentinel,
+    }
+  }
+}
+
+impl From<FragmentError> for GrplStatus {
+  fn from(value: FragmentError) -> Self {
+    match value {
+      FragmentError::Marshal(e) => e.into(),
+      FragmentError::IdsExhausted => GrplStatus::FragmentIdsExhausted,
+    }
+  }
+}
+
+impl From<DefragmentError> for GrplStatus {
+  fn from(value: DefragmentError) -> Self {
+    match value {
+      DefragmentError::Marshal(e) => e.into(),
+      DefragmentError::Discontiguous => GrplStatus::Discontiguous,
+      DefragmentError::CrcMismatch { .. } => GrplStatus::CrcMismatch,
+    }
+  }
+}
+
+// Opaque handle to a decoded message. Free with grpl_message_free.
+pub struct GrplMessage(Message<'static>);
+
+// Opaque handle to a fragment reassembler. Free with grpl_reassembler_free.
+pub struct GrplReassembler {
+  decoder: MessageDecoder,
+  tx: FragmentReassemblerTx,
+}
+
+// Nullable in C, so a NULL callback is reported rather than called.
+pub type GrplFrameCallback = Option<extern "C" fn(user: *mut core::ffi::c_void, id: u32, data: *const u8, len: usize)>;
+
+unsafe fn slice<'a>(data: *const u8, len: usize) -> &'a [u8] {
+  if len == 0 { &[] } else { core::slice::from_raw_parts(data, len) }
+}
+
+fn into_handle(msg: Message<'static>) -> *mut GrplMessage {
+  alloc::boxed::Box::into_raw(alloc::boxed::Box::new(GrplMessage(msg)))
+}
+
+/// Decode a single frame. Fragments are returned as-is, use a reassembler to put them back together.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_decode(id: u32, data: *const u8, len: usize, out: *mut *mut GrplMessage) -> GrplStatus {
+  if (data.is_null() && len > 0) || out.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  match MessageDecoder::decode_raw(id.into(), slice(data, len)) {
+    Ok(msg) => {
+      *out = into_handle(msg.into_static());
+      GrplStatus::Ok
+    },
+    Err(e) => e.into(),
+  }
+}
+
+/// # Safety
+/// `json` must be a valid, NUL-terminated string and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_from_json(json: *const c_char, out: *mut *mut GrplMessage) -> GrplStatus {
+  if json.is_null() || out.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  let json = match core::ffi::CStr::from_ptr(json).to_str() {
+    Ok(json) => json,
+    Err(_) => return GrplStatus::InvalidJson,
+  };
+
+  match serde_json::from_str::<Message>(json) {
+    Ok(mut msg) => {
+      msg.update(&mut ());
+      *out = into_handle(msg.into_static());
+      GrplStatus::Ok
+    },
+    Err(_) => GrplStatus::InvalidJson,
+  }
+}
+
+/// Write the message as NUL-terminated JSON into `buf`. `len_out` is set to the required length (excluding the
+/// terminator) even if the buffer is too small.
+///
+/// # Safety
+/// `msg` must be a valid handle, `buf` must point to `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_to_json(msg: *const GrplMessage, buf: *mut c_char, buf_len: usize, len_out: *mut usize) -> GrplStatus {
+  if msg.is_null() || (buf.is_null() && buf_len > 0) {
+    return GrplStatus::NullPointer;
+  }
+
+  let json = match serde_json::to_string(&(*msg).0) {
+    Ok(json) => json,
+    Err(_) => return GrplStatus::InvalidJson,
+  };
+
+  if !len_out.is_null() {
+    *len_out = json.len();
+  }
+  if json.len() + 1 > buf_len {
+    return GrplStatus::BufferTooSmall;
+  }
+
+  ptr::copy_nonoverlapping(json.as_ptr() as *const c_char, buf, json.len());
+  *buf.add(json.len()) = 0;
+  GrplStatus::Ok
+}
+
+/// # Safety
+/// `msg` must be a handle returned by this library, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_free(msg: *mut GrplMessage) {
+  if !msg.is_null() {
+    drop(alloc::boxed::Box::from_raw(msg));
+  }
+}
+
+/// The message's 29-bit CAN ID, or 0 if `msg` is NULL.
+///
+/// # Safety
+/// `msg` must be a valid handle, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_id(msg: *const GrplMessage) -> u32 {
+  if msg.is_null() {
+    return 0;
+  }
+  (*msg).0.id.into()
+}
+
+/// Encode as a single, unfragmented frame.
+///
+/// # Safety
+/// `msg` must be a valid handle, `buf` must point to `buf_len` writable bytes and `id_out` and `len_out` must be
+/// valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_encode(msg: *const GrplMessage, id_out: *mut u32, buf: *mut u8, buf_len: usize, len_out: *mut usize) -> GrplStatus {
+  if msg.is_null() || id_out.is_null() || len_out.is_null() || (buf.is_null() && buf_len > 0) {
+    return GrplStatus::NullPointer;
+  }
+
+  let msg = &(*msg).0;
+  let mut writer = VecBitWriter::new();
+  if let Err(e) = msg.msg.write(&mut writer, msg.id) {
+    return e.into();
+  }
+
+  let data = writer.slice();
+  *id_out = msg.id.into();
+  *len_out = data.len();
+  if data.len() > buf_len {
+    return GrplStatus::BufferTooSmall;
+  }
+  ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+  GrplStatus::Ok
+}
+
+fn device_message(msg: &GrplMessage) -> Option<&GrappleDeviceMessage<'static>> {
+  match &msg.0.msg {
+    ManufacturerMessage::Grapple(MaybeFragment::Message(m)) => Some(m),
+    _ => None,
+  }
+}
+
+/// # Safety
+/// `msg` must be a valid handle and `out` must be a valid pointer.
+#[cfg(feature = "grapple_lasercan")]
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_as_lasercan_measurement(msg: *const GrplMessage, out: *mut crate::grapple::lasercan::LaserCanMeasurement) -> GrplStatus {
+  use crate::grapple::lasercan::LaserCanMessage;
+
+  if msg.is_null() || out.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  match device_message(&*msg) {
+    Some(GrappleDeviceMessage::DistanceSensor(LaserCanMessage::Measurement(m))) => {
+      ptr::write(out, m.clone());
+      GrplStatus::Ok
+    },
+    _ => GrplStatus::WrongMessageType,
+  }
+}
+
+/// # Safety
+/// `msg` must be a valid handle and `out` must be a valid pointer.
+#[cfg(feature = "grapple_mitocandria")]
+#[no_mangle]
+pub unsafe extern "C" fn grpl_message_as_mitocandria_status(msg: *const GrplMessage, out: *mut crate::grapple::mitocandria::MitocandriaStatusFrame) -> GrplStatus {
+  use crate::grapple::mitocandria::MitocandriaMessage;
+
+  if msg.is_null() || out.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  match device_message(&*msg) {
+    Some(GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::StatusFrame(s))) => {
+      ptr::write(out, s.clone());
+      GrplStatus::Ok
+    },
+    _ => GrplStatus::WrongMessageType,
+  }
+}
+
+#[no_mangle]
+pub extern "C" fn grpl_reassembler_new(age_off: i64, max_fragment_size: usize) -> *mut GrplReassembler {
+  let (_, tx) = FragmentReassembler::new(age_off, max_fragment_size).split();
+  alloc::boxed::Box::into_raw(alloc::boxed::Box::new(GrplReassembler { decoder: MessageDecoder::new(age_off), tx }))
+}
+
+/// # Safety
+/// `reassembler` must be a handle returned by grpl_reassembler_new, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_reassembler_free(reassembler: *mut GrplReassembler) {
+  if !reassembler.is_null() {
+    drop(alloc::boxed::Box::from_raw(reassembler));
+  }
+}
+
+/// Feed a received frame. `out` is set to the decoded message once complete, or NULL if more fragments are needed.
+///
+/// # Safety
+/// `reassembler` must be a valid handle, `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_reassembler_defragment(reassembler: *mut GrplReassembler, now: i64, id: u32, data: *const u8, len: usize, out: *mut *mut GrplMessage) -> GrplStatus {
+  if reassembler.is_null() || (data.is_null() && len > 0) || out.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  *out = ptr::null_mut();
+  let frame = CanFrame::new(id.into(), now, slice(data, len));
+  match (*reassembler).decoder.decode(&frame) {
+    Ok(Some(msg)) => {
+      *out = into_handle(msg);
+      GrplStatus::Ok
+    },
+    Ok(None) => GrplStatus::Ok,
+    Err(e) => e.into(),
+  }
+}
+
+/// Encode a message into frames, fragmenting if required. `callback` is called once per frame, in order.
+///
+/// # Safety
+/// `reassembler` and `msg` must be valid handles.
+#[no_mangle]
+pub unsafe extern "C" fn grpl_reassembler_fragment(reassembler: *mut GrplReassembler, msg: *const GrplMessage, callback: GrplFrameCallback, user: *mut core::ffi::c_void) -> GrplStatus {
+  let callback = match callback {
+    Some(callback) => callback,
+    None => return GrplStatus::NullPointer,
+  };
+  if reassembler.is_null() || msg.is_null() {
+    return GrplStatus::NullPointer;
+  }
+
+  let result = encode_message(&mut (*reassembler).tx, (*msg).0.clone(), &mut |id, data| {
+    callback(user, id.into(), data.as_ptr(), data.len())
+  });
+
+  match result {
+    Ok(()) => GrplStatus::Ok,
+    Err(e) => e.into(),
+  }
+}
+
+fn device_handle(device_id: u8, msg: GrappleDeviceMessage<'static>) -> *mut GrplMessage {
+  into_handle(Message::new(device_id, ManufacturerMessage::Grapple(MaybeFragment::Message(msg))))
+}
+
+/// `mode` is 0 for short range, 1 for long range. Returns NULL if out of range.
+#[cfg(feature = "grapple_lasercan")]
+#[no_mangle]
+pub extern "C" fn grpl_lasercan_set_range(device_id: u8, mode: u8) -> *mut GrplMessage {
+  use crate::grapple::{Request, lasercan::{LaserCanMessage, LaserCanRangingMode}};
+
+  let mode = match mode {
+    0 => LaserCanRangingMode::Short,
+    1 => LaserCanRangingMode::Long,
+    _ => return ptr::null_mut(),
+  };
+  device_handle(device_id, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetRange(Request::Request(mode))))
+}
+
+/// `budget_ms` must be one of 20, 33, 50 or 100. Returns NULL otherwise.
+#[cfg(feature = "grapple_lasercan")]
+#[no_mangle]
+pub extern "C" fn grpl_lasercan_set_timing_budget(device_id: u8, budget_ms: u8) -> *mut GrplMessage {
+  use crate::grapple::{Request, lasercan::{LaserCanMessage, LaserCanTimingBudget}};
+
+  let budget = match budget_ms {
+    20 => LaserCanTimingBudget::TB20ms,
+    33 => LaserCanTimingBudget::TB33ms,
+    50 => LaserCanTimingBudget::TB50ms,
+    100 => LaserCanTimingBudget::TB100ms,
+    _ => return ptr::null_mut(),
+  };
+  device_handle(device_id, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetTimingBudget(Request::Request(budget))))
+}
+
+/// Returns NULL if the ROI is invalid.
+#[cfg(feature = "grapple_lasercan")]
+#[no_mangle]
+pub extern "C" fn grpl_lasercan_set_roi(device_id: u8, x: u8, y: u8, w: u8, h: u8) -> *mut GrplMessage {
+  use crate::{Validate, grapple::{Request, lasercan::{LaserCanMessage, LaserCanRoi, LaserCanRoiU4}}};
+
+  let roi = LaserCanRoi { x: LaserCanRoiU4(x), y: LaserCanRoiU4(y), w: LaserCanRoiU4(w), h: LaserCanRoiU4(h) };
+  if roi.validate().is_err() {
+    return ptr::null_mut();
+  }
+  device_handle(device_id, GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetRoi(Request::Request(roi))))
+}
+
+#[cfg(feature = "grapple_mitocandria")]
+#[no_mangle]
+pub extern "C" fn grpl_mitocandria_set_switchable_channel(device_id: u8, channel: u8, enabled: bool) -> *mut GrplMessage {
+  use crate::grapple::{Request, mitocandria::{MitocandriaChannelRequest, MitocandriaMessage, MitocandriaSwitchableChannelRequest}};
+
+  device_handle(device_id, GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::ChannelRequest(
+    MitocandriaChannelRequest::SetSwitchableChannel(Request::Request(MitocandriaSwitchableChannelRequest { channel, enabled }))
+  )))
+}
+
+#[cfg(feature = "grapple_mitocandria")]
+#[no_mangle]
+pub extern "C" fn grpl_mitocandria_set_adjustable_channel(device_id: u8, channel: u8, voltage: u16) -> *mut GrplMessage {
+  use crate::grapple::{Request, mitocandria::{MitocandriaAdjustableChannelRequest, MitocandriaChannelRequest, MitocandriaMessage}};
+
+  device_handle(device_id, GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::ChannelRequest(
+    MitocandriaChannelRequest::SetAdjustableChannel(Request::Request(MitocandriaAdjustableChannelRequest { channel, voltage }))
+  )))
+}
@@ -0,0 +1,207 @@
+use std::io::{BufRead, Write};
+
+use crate::{Message, MessageId, can::CanFrameFormat, grapple::fragments::{FragmentReassembler, FragmentReassemblerTx}, transport::{CanFrame, CanTransport, MessageDecoder, encode_message}};
+
+use super::CaptureError;
+
+// candump prints SocketCAN error frames with this bit set in the ID
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+
+// A single line of a `candump -l` log, e.g. `(1436509052.249713) can0 0C0180C1#DEADBEEF`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandumpEntry {
+  pub interface: String,
+  pub format: CanFrameFormat,
+  // CAN FD flags nibble (BRS = 0x1, ESI = 0x2). Always 0 for classic frames.
+  pub fd_flags: u8,
+  pub frame: CanFrame,
+}
+
+// Parses pairs of hex digits, e.g. `DEADBEEF`. Anything else, including non-ASCII text, is Malformed.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, CaptureError> {
+  let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+  let bytes = s.as_bytes();
+  if !bytes.len().is_multiple_of(2) {
+    return Err(CaptureError::Malformed(format!("Odd length data: {}", s)));
+  }
+  bytes.chunks(2)
+    .map(|pair| match (digit(pair[0]), digit(pair[1])) {
+      (Some(hi), Some(lo)) => Ok((hi << 4) | lo),
+      _ => Err(CaptureError::Malformed(format!("Invalid data: {}", s))),
+    })
+    .collect()
+}
+
+pub fn parse_line(line: &str) -> Result<CandumpEntry, CaptureError> {
+  let mut parts = line.split_whitespace();
+  let (ts, interface, frame) = match (parts.next(), parts.next(), parts.next()) {
+    (Some(ts), Some(interface), Some(frame)) => (ts, interface, frame),
+    _ => return Err(CaptureError::Malformed(format!("Expected (timestamp) interface frame: {}", line)))
+  };
+
+  let ts = ts.strip_prefix('(').and_then(|x| x.strip_suffix(')'))
+    .ok_or_else(|| CaptureError::Malformed(format!("Invalid timestamp: {}", ts)))?;
+  let (secs, micros) = ts.split_once('.').unwrap_or((ts, "0"));
+  let invalid_ts = || CaptureError::Malformed(format!("Invalid timestamp: {}", ts));
+  if secs.is_empty() || micros.is_empty() || !secs.bytes().chain(micros.bytes()).all(|c| c.is_ascii_digit()) {
+    return Err(invalid_ts());
+  }
+  let secs: i64 = secs.parse().map_err(|_| invalid_ts())?;
+  let micros: i64 = format!("{:0<6.6}", micros).parse().map_err(|_| invalid_ts())?;
+  let timestamp = secs.checked_mul(1_000_000).and_then(|x| x.checked_add(micros)).ok_or_else(invalid_ts)?;
+
+  let (id, data) = frame.split_once('#')
+    .ok_or_else(|| CaptureError::Malformed(format!("Invalid frame: {}", frame)))?;
+
+  if id.len() != 8 {
+    return Err(CaptureError::Unsupported(format!("Standard (11-bit) ID: {}", id)));
+  }
+  let id = u32::from_str_radix(id, 16).map_err(|_| CaptureError::Malformed(format!("Invalid ID: {}", id)))?;
+  if id & CAN_ERR_FLAG != 0 {
+    return Err(CaptureError::Unsupported(format!("Error frame: {}", frame)));
+  }
+
+  let (format, fd_flags, data) = match data.strip_prefix('#') {
+    Some(fd) => {
+      let flags = fd.get(0..1).and_then(|x| u8::from_str_radix(x, 16).ok())
+        .ok_or_else(|| CaptureError::Malformed(format!("Invalid FD flags: {}", frame)))?;
+      (CanFrameFormat::Fd, flags, &fd[1..])
+    },
+    None if data.starts_with('R') => return Err(CaptureError::Unsupported(format!("Remote frame: {}", frame))),
+    None => (CanFrameFormat::Classic, 0, data),
+  };
+
+  // Strip the optional `_<dlc>` suffix candump adds for classic frames with a non-standard DLC
+  let data = data.split_once('_').map(|x| x.0).unwrap_or(data);
+  let data = parse_hex(data)?;
+  if data.len() > format.max_data_len() {
+    return Err(CaptureError::Malformed(format!("Frame too long: {}", frame)));
+  }
+
+  Ok(CandumpEntry {
+    interface: interface.to_owned(),
+    format,
+    fd_flags,
+    frame: CanFrame::new(MessageId::from(id), timestamp, &data),
+  })
+}
+
+pub fn format_line(entry: &CandumpEntry) -> String {
+  let data: String = entry.frame.data.iter().map(|b| format!("{:02X}", b)).collect();
+  let sep = match entry.format {
+    CanFrameFormat::Classic => "#".to_owned(),
+    CanFrameFormat::Fd => format!("##{:X}", entry.fd_flags & 0xF),
+  };
+  format!(
+    "({}.{:06}) {} {:08X}{}{}",
+    entry.frame.timestamp.div_euclid(1_000_000), entry.frame.timestamp.rem_euclid(1_000_000),
+    entry.interface, Into::<u32>::into(entry.frame.id), sep, data
+  )
+}
+
+pub struct CandumpReader<R: BufRead> {
+  reader: R,
+  decoder: MessageDecoder,
+  last_timestamp: i64,
+  line: String,
+}
+
+impl<R: BufRead> CandumpReader<R> {
+  pub fn new(reader: R, age_off: i64) -> Self {
+    Self { reader, decoder: MessageDecoder::new(age_off), last_timestamp: 0, line: String::new() }
+  }
+
+  // Next supported entry in the log. Standard ID, remote and error frames are skipped.
+  pub fn next_entry(&mut self) -> Result<Option<CandumpEntry>, CaptureError> {
+    loop {
+      self.line.clear();
+      if self.reader.read_line(&mut self.line)? == 0 {
+        return Ok(None);
+      }
+
+      let line = self.line.trim();
+      if line.is_empty() {
+        continue;
+      }
+
+      match parse_line(line) {
+        Ok(entry) => {
+          self.last_timestamp = entry.frame.timestamp;
+          return Ok(Some(entry))
+        },
+        Err(CaptureError::Unsupported(_)) => continue,
+        Err(e) => return Err(e),
+      }
+    }
+  }
+
+  // Next fully decoded message in the log, reassembling fragments.
+  pub fn next_message(&mut self) -> Result<Option<(i64, Message<'static>)>, CaptureError> {
+    while let Some(entry) = self.next_entry()? {
+      if let Some(msg) = self.decoder.decode(&entry.frame)? {
+        return Ok(Some((entry.frame.timestamp, msg)));
+      }
+    }
+    Ok(None)
+  }
+}
+
+// Replays a log as if it were a live bus. Sending is not supported.
+impl<R: BufRead> CanTransport for CandumpReader<R> {
+  type Error = CaptureError;
+
+  fn send(&mut self, _id: MessageId, _data: &[u8]) -> Result<(), Self::Error> {
+    Err(CaptureError::Unsupported("Cannot send to a candump log".to_owned()))
+  }
+
+  fn recv(&mut self) -> Result<Option<CanFrame>, Self::Error> {
+    Ok(self.next_entry()?.map(|e| e.frame))
+  }
+
+  fn now(&self) -> i64 {
+    self.last_timestamp
+  }
+}
+
+pub struct CandumpWriter<W: Write> {
+  writer: W,
+  interface: String,
+  tx: FragmentReassemblerTx,
+  format: CanFrameFormat,
+}
+
+impl<W: Write> CandumpWriter<W> {
+  pub fn new(writer: W, interface: &str, format: CanFrameFormat) -> Self {
+    let (_, tx) = FragmentReassembler::new_for_format(0, format).split();
+    Self { writer, interface: interface.to_owned(), tx, format }
+  }
+
+  pub fn write_entry(&mut self, entry: &CandumpEntry) -> Result<(), CaptureError> {
+    writeln!(self.writer, "{}", format_line(entry))?;
+    Ok(())
+  }
+
+  pub fn write_frame(&mut self, frame: &CanFrame) -> Result<(), CaptureError> {
+    let entry = CandumpEntry { interface: self.interface.clone(), format: self.format, fd_flags: 0, frame: frame.clone() };
+    self.write_entry(&entry)
+  }
+
+  // Write a message, fragmenting it if required. All resulting frames share the same timestamp.
+  pub fn write_message(&mut self, timestamp: i64, message: Message) -> Result<(), CaptureError> {
+    let mut frames = vec![];
+    encode_message(&mut self.tx, message, &mut |id, data| frames.push(CanFrame::new(id, timestamp, data)))?;
+    for frame in &frames {
+      self.write_frame(frame)?;
+    }
+    Ok(())
+  }
+
+  pub fn flush(&mut self) -> Result<(), CaptureError> {
+    self.writer.flush()?;
+    Ok(())
+  }
+
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+}
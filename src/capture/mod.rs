@@ -0,0 +1,55 @@
+use std::{fmt, io};
+
+use crate::grapple::fragments::{DefragmentError, FragmentError};
+
+pub mod candump;
+pub mod pcap;
+
+#[derive(Debug)]
+pub enum CaptureError {
+  Io(io::Error),
+  Malformed(String),
+  Unsupported(String),
+  Marshal(binmarshal::MarshalError),
+  Fragment(FragmentError),
+  Defragment(DefragmentError),
+}
+
+impl fmt::Display for CaptureError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CaptureError::Io(e) => write!(f, "IO Error: {}", e),
+      CaptureError::Malformed(msg) => write!(f, "Malformed Capture: {}", msg),
+      CaptureError::Unsupported(msg) => write!(f, "Unsupported Frame: {}", msg),
+      CaptureError::Marshal(e) => write!(f, "Marshal Error: {:?}", e),
+      CaptureError::Fragment(e) => write!(f, "{}", e),
+      CaptureError::Defragment(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl From<io::Error> for CaptureError {
+  fn from(value: io::Error) -> Self {
+    CaptureError::Io(value)
+  }
+}
+
+impl From<binmarshal::MarshalError> for CaptureError {
+  fn from(value: binmarshal::MarshalError) -> Self {
+    CaptureError::Marshal(value)
+  }
+}
+
+impl From<FragmentError> for CaptureError {
+  fn from(value: FragmentError) -> Self {
+    CaptureError::Fragment(value)
+  }
+}
+
+impl From<DefragmentError> for CaptureError {
+  fn from(value: DefragmentError) -> Self {
+    CaptureError::Defragment(value)
+  }
+}
@@ -0,0 +1,409 @@
+use std::io::{Read, Write};
+
+use binmarshal::{AsymmetricCow, LengthTaggedPayload};
+use alloc::borrow::Cow;
+
+use crate::{Message, MessageId, bridge::BridgedCANMessage, can::{CanFrameFormat, CANFD_MAX_DATA_LEN}, grapple::{encapsulation::EncapsulatedMesssage, fragments::{FragmentReassembler, FragmentReassemblerTx}}, transport::{CanFrame, MessageDecoder, encode_message_on_channel}};
+
+use super::CaptureError;
+
+pub const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+const CAN_EFF_FLAG: u32 = 0x8000_0000;
+const CAN_RTR_FLAG: u32 = 0x4000_0000;
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_EFF_MASK: u32 = 0x1FFF_FFFF;
+const CANFD_FDF: u8 = 0x04;
+
+const BLOCK_SHB: u32 = 0x0A0D_0D0A;
+const BLOCK_IDB: u32 = 0x0000_0001;
+const BLOCK_SPB: u32 = 0x0000_0003;
+const BLOCK_EPB: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const OPTION_IF_TSRESOL: u16 = 9;
+
+const PCAP_MAGIC_MICROS: u32 = 0xA1B2_C3D4;
+const PCAP_MAGIC_NANOS: u32 = 0xA1B2_3C4D;
+
+const SOCKETCAN_HEADER_LEN: usize = 8;
+
+// Far larger than any block or record a SocketCAN capture holds, but small enough that a corrupt length
+// can't make us allocate gigabytes
+const MAX_BLOCK_LEN: usize = 1 << 20;
+
+// A frame read from a capture. The channel is the pcapng interface ID, matching EncapsulatedMesssage's channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PcapRecord {
+  pub channel: u8,
+  pub format: CanFrameFormat,
+  pub frame: CanFrame,
+}
+
+impl PcapRecord {
+  pub fn to_bridged(&self) -> BridgedCANMessage<'_> {
+    BridgedCANMessage {
+      id: self.frame.id,
+      timestamp: self.frame.timestamp as u32,
+      data: AsymmetricCow(Cow::Borrowed(Into::<&LengthTaggedPayload<u8>>::into(&self.frame.data[..]))),
+    }
+  }
+
+  pub fn to_encapsulated(&self) -> EncapsulatedMesssage<'_> {
+    EncapsulatedMesssage {
+      channel: self.channel,
+      timestamp: self.frame.timestamp as u32,
+      id: self.frame.id,
+      data: AsymmetricCow(Cow::Borrowed(Into::<&LengthTaggedPayload<u8>>::into(&self.frame.data[..]))),
+    }
+  }
+}
+
+// As on the bus, CAN FD payloads are padded with zeros up to the next valid DLC length.
+fn encode_socketcan(format: CanFrameFormat, frame: &CanFrame) -> Result<Vec<u8>, CaptureError> {
+  let len = format.padded_len(frame.data.len())
+    .ok_or_else(|| CaptureError::Malformed(format!("Frame too long: {} bytes", frame.data.len())))?;
+
+  let mut out = Vec::with_capacity(SOCKETCAN_HEADER_LEN + len);
+  out.extend_from_slice(&(Into::<u32>::into(frame.id) | CAN_EFF_FLAG).to_be_bytes());
+  out.push(len as u8);
+  out.push(match format {
+    CanFrameFormat::Classic => 0,
+    CanFrameFormat::Fd => CANFD_FDF,
+  });
+  out.extend_from_slice(&[0, 0]);
+  out.extend_from_slice(&frame.data[..]);
+  out.resize(SOCKETCAN_HEADER_LEN + len, 0);
+  Ok(out)
+}
+
+fn decode_socketcan(channel: u8, timestamp: i64, data: &[u8]) -> Result<PcapRecord, CaptureError> {
+  if data.len() < SOCKETCAN_HEADER_LEN {
+    return Err(CaptureError::Malformed("SocketCAN header truncated".to_owned()));
+  }
+
+  let can_id = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+  if can_id & CAN_EFF_FLAG == 0 {
+    return Err(CaptureError::Unsupported(format!("Standard (11-bit) ID: {:03X}", can_id)));
+  }
+  if can_id & (CAN_RTR_FLAG | CAN_ERR_FLAG) != 0 {
+    return Err(CaptureError::Unsupported("Remote or error frame".to_owned()));
+  }
+
+  let len = data[4] as usize;
+  if len > CANFD_MAX_DATA_LEN {
+    return Err(CaptureError::Malformed(format!("SocketCAN payload too long: {} bytes", len)));
+  }
+  let format = if data[5] & CANFD_FDF != 0 || len > 8 { CanFrameFormat::Fd } else { CanFrameFormat::Classic };
+  let payload = data.get(SOCKETCAN_HEADER_LEN..SOCKETCAN_HEADER_LEN + len)
+    .ok_or_else(|| CaptureError::Malformed("SocketCAN payload truncated".to_owned()))?;
+
+  Ok(PcapRecord { channel, format, frame: CanFrame::new(MessageId::from(can_id & CAN_EFF_MASK), timestamp, payload) })
+}
+
+fn pad4(len: usize) -> usize {
+  (len + 3) & !3
+}
+
+// Writes a pcapng capture with one SocketCAN interface per channel. Timestamps are in microseconds.
+pub struct PcapngWriter<W: Write> {
+  writer: W,
+  format: CanFrameFormat,
+  n_interfaces: usize,
+  tx: FragmentReassemblerTx,
+}
+
+impl<W: Write> PcapngWriter<W> {
+  pub fn new(mut writer: W, format: CanFrameFormat) -> Result<Self, CaptureError> {
+    let mut shb = vec![];
+    shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    shb.extend_from_slice(&1u16.to_le_bytes());
+    shb.extend_from_slice(&0u16.to_le_bytes());
+    shb.extend_from_slice(&(-1i64).to_le_bytes());
+    Self::write_block(&mut writer, BLOCK_SHB, &shb)?;
+
+    let (_, tx) = FragmentReassembler::new_for_format(0, format).split();
+    Ok(Self { writer, format, n_interfaces: 0, tx })
+  }
+
+  fn write_block(writer: &mut W, block_type: u32, body: &[u8]) -> Result<(), CaptureError> {
+    let total_len = (12 + pad4(body.len())) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&[0u8; 3][..pad4(body.len()) - body.len()])?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    Ok(())
+  }
+
+  fn ensure_interface(&mut self, channel: u8) -> Result<(), CaptureError> {
+    while self.n_interfaces <= channel as usize {
+      let mut idb = vec![];
+      idb.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+      idb.extend_from_slice(&0u16.to_le_bytes());
+      idb.extend_from_slice(&((SOCKETCAN_HEADER_LEN + self.format.max_data_len()) as u32).to_le_bytes());
+      Self::write_block(&mut self.writer, BLOCK_IDB, &idb)?;
+      self.n_interfaces += 1;
+    }
+    Ok(())
+  }
+
+  pub fn write_frame(&mut self, channel: u8, frame: &CanFrame) -> Result<(), CaptureError> {
+    self.ensure_interface(channel)?;
+
+    let packet = encode_socketcan(self.format, frame)?;
+    let ts = frame.timestamp as u64;
+
+    let mut epb = vec![];
+    epb.extend_from_slice(&(channel as u32).to_le_bytes());
+    epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+    epb.extend_from_slice(&(ts as u32).to_le_bytes());
+    epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    epb.extend_from_slice(&packet);
+    epb.resize(pad4(epb.len()), 0);
+    Self::write_block(&mut self.writer, BLOCK_EPB, &epb)
+  }
+
+  pub fn write_bridged(&mut self, msg: &BridgedCANMessage) -> Result<(), CaptureError> {
+    self.write_frame(0, &CanFrame::new(msg.id, msg.timestamp as i64, &msg.data[..]))
+  }
+
+  pub fn write_encapsulated(&mut self, msg: &EncapsulatedMesssage) -> Result<(), CaptureError> {
+    self.write_frame(msg.channel, &CanFrame::new(msg.id, msg.timestamp as i64, &msg.data[..]))
+  }
+
+  // Write a message, fragmenting it if required. All resulting frames share the same timestamp.
+  pub fn write_message(&mut self, channel: u8, timestamp: i64, message: Message) -> Result<(), CaptureError> {
+    let mut frames = vec![];
+    encode_message_on_channel(&mut self.tx, channel, message, &mut |id, data| frames.push(CanFrame::new(id, timestamp, data)))?;
+    for frame in &frames {
+      self.write_frame(channel, frame)?;
+    }
+    Ok(())
+  }
+
+  pub fn flush(&mut self) -> Result<(), CaptureError> {
+    self.writer.flush()?;
+    Ok(())
+  }
+
+  pub fn into_inner(self) -> W {
+    self.writer
+  }
+}
+
+struct PcapngInterface {
+  linktype: u16,
+  // Timestamp units per second
+  ts_per_sec: u64,
+}
+
+enum PcapFormat {
+  Pcap { big_endian: bool, ts_per_sec: u64, linktype: u16 },
+  Pcapng { big_endian: bool, interfaces: Vec<PcapngInterface> },
+}
+
+// Reads SocketCAN frames from either a classic pcap or a pcapng capture. Frames with other link types,
+// standard IDs, remote or error frames are skipped.
+pub struct PcapReader<R: Read> {
+  reader: R,
+  format: PcapFormat,
+  decoder: MessageDecoder,
+}
+
+fn read_u16(buf: &[u8], big_endian: bool) -> u16 {
+  let b = [buf[0], buf[1]];
+  if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) }
+}
+
+fn read_u32(buf: &[u8], big_endian: bool) -> u32 {
+  let b = [buf[0], buf[1], buf[2], buf[3]];
+  if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }
+}
+
+// Checks a pcapng block's total length, returning the length of the rest of the block after `read` bytes
+fn block_remaining(total_len: usize, read: usize) -> Result<usize, CaptureError> {
+  if total_len < 12 {
+    return Err(CaptureError::Malformed(format!("Block too short: {} bytes", total_len)));
+  }
+  if total_len > MAX_BLOCK_LEN {
+    return Err(CaptureError::Malformed(format!("Block too long: {} bytes", total_len)));
+  }
+  Ok(total_len - read)
+}
+
+// Saturates rather than wrapping, since a corrupt timestamp shouldn't go negative
+fn to_micros(ts: u64, ts_per_sec: u64) -> i64 {
+  i64::try_from((ts as u128 * 1_000_000) / ts_per_sec as u128).unwrap_or(i64::MAX)
+}
+
+impl<R: Read> PcapReader<R> {
+  pub fn new(mut reader: R, age_off: i64) -> Result<Self, CaptureError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    let format = if u32::from_le_bytes(magic) == BLOCK_SHB {
+      let mut len = [0u8; 4];
+      reader.read_exact(&mut len)?;
+      let mut bom = [0u8; 4];
+      reader.read_exact(&mut bom)?;
+      let big_endian = u32::from_be_bytes(bom) == BYTE_ORDER_MAGIC;
+      let mut rest = vec![0u8; block_remaining(read_u32(&len, big_endian) as usize, 12)?];
+      reader.read_exact(&mut rest)?;
+      PcapFormat::Pcapng { big_endian, interfaces: vec![] }
+    } else {
+      let (big_endian, ts_per_sec) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+        (PCAP_MAGIC_MICROS, _) => (false, 1_000_000),
+        (PCAP_MAGIC_NANOS, _) => (false, 1_000_000_000),
+        (_, PCAP_MAGIC_MICROS) => (true, 1_000_000),
+        (_, PCAP_MAGIC_NANOS) => (true, 1_000_000_000),
+        _ => return Err(CaptureError::Malformed("Not a pcap or pcapng file".to_owned()))
+      };
+      let mut header = [0u8; 20];
+      reader.read_exact(&mut header)?;
+      PcapFormat::Pcap { big_endian, ts_per_sec, linktype: read_u32(&header[16..20], big_endian) as u16 }
+    };
+
+    Ok(Self { reader, format, decoder: MessageDecoder::new(age_off) })
+  }
+
+  // Reads exactly `buf.len()` bytes, returning false on a clean EOF at the start of a record.
+  fn read_or_eof(&mut self, buf: &mut [u8]) -> Result<bool, CaptureError> {
+    let mut n = 0;
+    while n < buf.len() {
+      match self.reader.read(&mut buf[n..])? {
+        0 if n == 0 => return Ok(false),
+        0 => return Err(CaptureError::Malformed("Unexpected end of capture".to_owned())),
+        x => n += x,
+      }
+    }
+    Ok(true)
+  }
+
+  pub fn next_record(&mut self) -> Result<Option<PcapRecord>, CaptureError> {
+    loop {
+      let result = match &self.format {
+        PcapFormat::Pcap { .. } => self.next_pcap()?,
+        PcapFormat::Pcapng { .. } => self.next_pcapng()?,
+      };
+
+      match result {
+        None => return Ok(None),
+        Some(Ok(record)) => return Ok(Some(record)),
+        Some(Err(CaptureError::Unsupported(_))) => continue,
+        Some(Err(e)) => return Err(e),
+      }
+    }
+  }
+
+  fn next_pcap(&mut self) -> Result<Option<Result<PcapRecord, CaptureError>>, CaptureError> {
+    let (big_endian, ts_per_sec, linktype) = match self.format {
+      PcapFormat::Pcap { big_endian, ts_per_sec, linktype } => (big_endian, ts_per_sec, linktype),
+      _ => unreachable!(),
+    };
+
+    let mut header = [0u8; 16];
+    if !self.read_or_eof(&mut header)? {
+      return Ok(None);
+    }
+    let secs = read_u32(&header[0..4], big_endian) as u64;
+    let frac = read_u32(&header[4..8], big_endian) as u64;
+    let captured = read_u32(&header[8..12], big_endian) as usize;
+    if captured > MAX_BLOCK_LEN {
+      return Err(CaptureError::Malformed(format!("Record too long: {} bytes", captured)));
+    }
+    let mut data = vec![0u8; captured];
+    self.reader.read_exact(&mut data)?;
+
+    if linktype != LINKTYPE_CAN_SOCKETCAN {
+      return Ok(Some(Err(CaptureError::Unsupported(format!("Link type {}", linktype)))));
+    }
+    Ok(Some(decode_socketcan(0, to_micros(secs * ts_per_sec + frac, ts_per_sec), &data)))
+  }
+
+  fn next_pcapng(&mut self) -> Result<Option<Result<PcapRecord, CaptureError>>, CaptureError> {
+    let mut header = [0u8; 8];
+    if !self.read_or_eof(&mut header)? {
+      return Ok(None);
+    }
+
+    let block_type = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+    if block_type == BLOCK_SHB {
+      // New section, which may change byte order and resets interfaces
+      let mut bom = [0u8; 4];
+      self.reader.read_exact(&mut bom)?;
+      let big_endian = u32::from_be_bytes(bom) == BYTE_ORDER_MAGIC;
+      let mut rest = vec![0u8; block_remaining(read_u32(&header[4..8], big_endian) as usize, 12)?];
+      self.reader.read_exact(&mut rest)?;
+      self.format = PcapFormat::Pcapng { big_endian, interfaces: vec![] };
+      return Ok(Some(Err(CaptureError::Unsupported("Section header".to_owned()))));
+    }
+
+    let PcapFormat::Pcapng { big_endian, interfaces } = &mut self.format else { unreachable!() };
+    let big_endian = *big_endian;
+    let block_type = read_u32(&header[0..4], big_endian);
+    let mut body = vec![0u8; block_remaining(read_u32(&header[4..8], big_endian) as usize, 8)?];
+    self.reader.read_exact(&mut body)?;
+    let body = &body[..body.len() - 4];
+
+    match block_type {
+      BLOCK_IDB if body.len() >= 8 => {
+        let linktype = read_u16(&body[0..2], big_endian);
+        let mut ts_per_sec = 1_000_000u64;
+
+        let mut opts = &body[8..];
+        while opts.len() >= 4 {
+          let code = read_u16(&opts[0..2], big_endian);
+          let len = read_u16(&opts[2..4], big_endian) as usize;
+          let value = opts.get(4..4 + len).unwrap_or(&[]);
+          if code == OPTION_IF_TSRESOL && !value.is_empty() {
+            let v = value[0];
+            let resolution = if v & 0x80 == 0 { 10u64.checked_pow((v & 0x7F) as u32) } else { 1u64.checked_shl((v & 0x7F) as u32) };
+            ts_per_sec = resolution.ok_or_else(|| CaptureError::Malformed(format!("Invalid timestamp resolution: {:#04x}", v)))?;
+          }
+          if code == 0 {
+            break;
+          }
+          opts = opts.get(4 + pad4(len)..).unwrap_or(&[]);
+        }
+
+        interfaces.push(PcapngInterface { linktype, ts_per_sec });
+        Ok(Some(Err(CaptureError::Unsupported("Interface description".to_owned()))))
+      },
+      BLOCK_EPB if body.len() >= 20 => {
+        let interface = read_u32(&body[0..4], big_endian) as usize;
+        let ts = ((read_u32(&body[4..8], big_endian) as u64) << 32) | read_u32(&body[8..12], big_endian) as u64;
+        let captured = read_u32(&body[12..16], big_endian) as usize;
+        let data = body.get(20..).and_then(|x| x.get(..captured)).ok_or_else(|| CaptureError::Malformed("Packet truncated".to_owned()))?;
+
+        let iface = interfaces.get(interface).ok_or_else(|| CaptureError::Malformed(format!("Unknown interface {}", interface)))?;
+        if iface.linktype != LINKTYPE_CAN_SOCKETCAN {
+          return Ok(Some(Err(CaptureError::Unsupported(format!("Link type {}", iface.linktype)))));
+        }
+        let channel = u8::try_from(interface).map_err(|_| CaptureError::Malformed(format!("Interface {} out of range", interface)))?;
+        Ok(Some(decode_socketcan(channel, to_micros(ts, iface.ts_per_sec), data)))
+      },
+      BLOCK_SPB if body.len() >= 4 => {
+        // Simple packets carry no timestamp and always belong to the first interface
+        let original = read_u32(&body[0..4], big_endian) as usize;
+        let data = &body[4..body.len().min(4 + original)];
+        match interfaces.first() {
+          Some(iface) if iface.linktype == LINKTYPE_CAN_SOCKETCAN => Ok(Some(decode_socketcan(0, 0, data))),
+          _ => Ok(Some(Err(CaptureError::Unsupported("Simple packet on non-SocketCAN interface".to_owned())))),
+        }
+      },
+      _ => Ok(Some(Err(CaptureError::Unsupported(format!("Block type {:#x}", block_type))))),
+    }
+  }
+
+  // Next fully decoded message in the capture, reassembling fragments separately for each channel.
+  pub fn next_message(&mut self) -> Result<Option<(u8, i64, Message<'static>)>, CaptureError> {
+    while let Some(record) = self.next_record()? {
+      if let Some(msg) = self.decoder.decode_on_channel(record.channel, &record.frame)? {
+        return Ok(Some((record.channel, record.frame.timestamp, msg)));
+      }
+    }
+    Ok(None)
+  }
+}
@@ -1,4 +1,4 @@
-use crate::Validate;
+use crate::{Validate, ValidateUtf8};
 use binmarshal::{Marshal, Demarshal, MarshalUpdate, AsymmetricCow};
 use bounded_static::ToStatic;
 
@@ -80,3 +80,17 @@ impl<'a> Validate for GrappleDeviceInfo<'a> {
     Ok(())
   }
 }
+
+impl<'a> ValidateUtf8 for GrappleDeviceInfo<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      GrappleDeviceInfo::EnumerateResponse { version, name, .. } => {
+        version.validate_utf8()?;
+        name.validate_utf8()
+      },
+      GrappleDeviceInfo::SetName { name, .. } => name.validate_utf8(),
+      GrappleDeviceInfo::EnumerateRequest | GrappleDeviceInfo::Blink { .. } | GrappleDeviceInfo::CommitConfig { .. }
+        | GrappleDeviceInfo::SetId { .. } | GrappleDeviceInfo::ArbitrationRequest | GrappleDeviceInfo::ArbitrationReject => Ok(())
+    }
+  }
+}
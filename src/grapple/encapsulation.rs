@@ -1,7 +1,7 @@
 use binmarshal::{AsymmetricCow, Demarshal, LengthTaggedPayload, LengthTaggedVec, Marshal, MarshalUpdate};
 use bounded_static::ToStatic;
 
-use crate::MessageId;
+use crate::{MessageId, ValidateUtf8};
 
 use super::{errors::GrappleResult, GrappleMessageId, Request};
 
@@ -41,4 +41,15 @@ pub enum BridgeMessages<'a> {
   ),
   #[marshal(tag = "3")]
   BridgeMessage(EncapsulatedMesssage<'a>)
-}
\ No newline at end of file
+}
+
+impl<'a> ValidateUtf8 for BridgeMessages<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      BridgeMessages::GetChannelName(req) => req.validate_utf8(),
+      BridgeMessages::StartBridge(req) => req.validate_utf8(),
+      BridgeMessages::StopBridge(req) => req.validate_utf8(),
+      BridgeMessages::BridgeMessage(_) => Ok(()),
+    }
+  }
+}
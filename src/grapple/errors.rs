@@ -7,6 +7,36 @@ use pyo3::prelude::*;
 #[cfg(feature = "pyo3")]
 use pyo3::BoundObject;
 
+// The bounds a parameter was outside of. `index` is the position of the offending field in the request.
+#[derive(Debug, Clone, PartialEq, Eq, ToStatic)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[repr(C)]
+pub struct ParameterRange {
+  pub index: u8,
+  pub min: i16,
+  pub max: i16,
+}
+
+// Written by hand, since the derives leave named fields unused
+impl Marshal<()> for ParameterRange {
+  fn write<W: binmarshal::BitWriter>(&self, writer: &mut W, _ctx: ()) -> Result<(), binmarshal::MarshalError> {
+    self.index.write(writer, ())?;
+    self.min.write(writer, ())?;
+    self.max.write(writer, ())
+  }
+}
+
+impl<'dm> Demarshal<'dm, ()> for ParameterRange {
+  fn read(view: &mut binmarshal::BitView<'dm>, _ctx: ()) -> Result<Self, binmarshal::MarshalError> {
+    Ok(Self { index: u8::read(view, ())?, min: i16::read(view, ())?, max: i16::read(view, ())? })
+  }
+}
+
+impl MarshalUpdate<()> for ParameterRange {
+  fn update(&mut self, _ctx: &mut ()) {}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "type", content = "data"))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -25,6 +55,29 @@ pub enum GrappleError<'a> {
     AsymmetricCow<'a, str>
   ),
 
+  // The errors below carry no text, so they fit in a single frame alongside the rest of an ack. The
+  // text is generated host-side by Display instead. Hosts that predate them can't decode them, so
+  // firmware should only send them to hosts known to understand them.
+
+  #[marshal(tag = "0x02")]
+  ParameterOutOfRange(ParameterRange),
+
+  #[marshal(tag = "0x03")]
+  Unsupported,
+
+  #[marshal(tag = "0x04")]
+  Busy,
+
+  #[marshal(tag = "0x05")]
+  InvalidState,
+
+  #[marshal(tag = "0x06")]
+  NotFound,
+
+  // The status reported by the device's flash driver
+  #[marshal(tag = "0x07")]
+  FlashError(u8),
+
   #[marshal(tag = "0xFE")]
   TimedOut(
     #[cfg_attr(feature = "serde", serde(borrow))]
@@ -38,41 +91,107 @@ pub enum GrappleError<'a> {
   ),
 }
 
-#[cfg(feature = "std")]
-impl<'a> std::fmt::Display for GrappleError<'a> {
+impl<'a> core::fmt::Display for GrappleError<'a> {
   fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
     match self {
       GrappleError::ParameterOutOfBounds(oob) => write!(f, "Parameter Out of Bounds: {}", oob.as_ref()),
       GrappleError::FailedAssertion(msg) => write!(f, "Failed Assertion: {}", msg.as_ref()),
+      GrappleError::ParameterOutOfRange(range) => write!(f, "Parameter Out of Range: parameter {} must be between {} and {}", range.index, range.min, range.max),
+      GrappleError::Unsupported => write!(f, "Unsupported Operation"),
+      GrappleError::Busy => write!(f, "Device Busy"),
+      GrappleError::InvalidState => write!(f, "Invalid State"),
+      GrappleError::NotFound => write!(f, "Not Found"),
+      GrappleError::FlashError(code) => write!(f, "Flash Error: code {:#04x}", code),
       GrappleError::TimedOut(msg) => write!(f, "Timed Out: {}", msg.as_ref()),
       GrappleError::Generic(str) => write!(f, "Generic Error: {}", str.as_ref()),
     }
   }
 }
 
+impl<'a> crate::ValidateUtf8 for GrappleError<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      GrappleError::ParameterOutOfBounds(msg) | GrappleError::FailedAssertion(msg) | GrappleError::TimedOut(msg) | GrappleError::Generic(msg) => msg.validate_utf8(),
+      GrappleError::ParameterOutOfRange(_) | GrappleError::Unsupported | GrappleError::Busy | GrappleError::InvalidState
+        | GrappleError::NotFound | GrappleError::FlashError(_) => Ok(()),
+    }
+  }
+}
+
+// The variant of a GrappleError without its data, for matching on a bare error code. The
+// discriminants are the error codes, which are also the marshal tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[repr(u8)]
+pub enum GrappleErrorKind {
+  ParameterOutOfBounds = 0x00,
+  FailedAssertion = 0x01,
+  ParameterOutOfRange = 0x02,
+  Unsupported = 0x03,
+  Busy = 0x04,
+  InvalidState = 0x05,
+  NotFound = 0x06,
+  FlashError = 0x07,
+  TimedOut = 0xFE,
+  Generic = 0xFF,
+}
+
+impl GrappleErrorKind {
+  pub fn from_error_code(code: u8) -> Option<Self> {
+    match code {
+      0x00 => Some(GrappleErrorKind::ParameterOutOfBounds),
+      0x01 => Some(GrappleErrorKind::FailedAssertion),
+      0x02 => Some(GrappleErrorKind::ParameterOutOfRange),
+      0x03 => Some(GrappleErrorKind::Unsupported),
+      0x04 => Some(GrappleErrorKind::Busy),
+      0x05 => Some(GrappleErrorKind::InvalidState),
+      0x06 => Some(GrappleErrorKind::NotFound),
+      0x07 => Some(GrappleErrorKind::FlashError),
+      0xFE => Some(GrappleErrorKind::TimedOut),
+      0xFF => Some(GrappleErrorKind::Generic),
+      _ => None,
+    }
+  }
+
+  pub fn error_code(self) -> u8 {
+    self as u8
+  }
+}
+
 // TODO: Build in get_tag() into binmarshal for this.
 impl<'a> GrappleError<'a> {
-  pub fn to_error_code(&self) -> u8 {
+  pub fn kind(&self) -> GrappleErrorKind {
     match self {
-      GrappleError::ParameterOutOfBounds(_) => 0x00,
-      GrappleError::FailedAssertion(_) => 0x01,
-      GrappleError::TimedOut(_) => 0xFE,
-      GrappleError::Generic(_) => 0xFF,
+      GrappleError::ParameterOutOfBounds(_) => GrappleErrorKind::ParameterOutOfBounds,
+      GrappleError::FailedAssertion(_) => GrappleErrorKind::FailedAssertion,
+      GrappleError::ParameterOutOfRange(_) => GrappleErrorKind::ParameterOutOfRange,
+      GrappleError::Unsupported => GrappleErrorKind::Unsupported,
+      GrappleError::Busy => GrappleErrorKind::Busy,
+      GrappleError::InvalidState => GrappleErrorKind::InvalidState,
+      GrappleError::NotFound => GrappleErrorKind::NotFound,
+      GrappleError::FlashError(_) => GrappleErrorKind::FlashError,
+      GrappleError::TimedOut(_) => GrappleErrorKind::TimedOut,
+      GrappleError::Generic(_) => GrappleErrorKind::Generic,
     }
   }
-}
 
-#[cfg(feature = "std")]
-impl<'a, E: std::error::Error> From<E> for GrappleError<'a> {
-  fn from(value: E) -> Self {
-    Self::Generic(AsymmetricCow(Cow::Owned(format!("{}", value))))
+  pub fn to_error_code(&self) -> u8 {
+    self.kind().error_code()
+  }
+
+  // A Generic error carrying the text of another error, for use with `map_err`
+  pub fn generic(err: impl core::fmt::Display) -> Self {
+    GrappleError::Generic(AsymmetricCow(Cow::Owned(alloc::format!("{}", err))))
   }
 }
 
-#[cfg(feature = "std")]
-impl<'a> From<GrappleError<'a>> for anyhow::Error {
-  fn from(value: GrappleError) -> Self {
-    anyhow::Error::msg(alloc::format!("{}", value))
+// With this, anyhow's own blanket impl converts GrappleError<'static> into anyhow::Error
+impl<'a> core::error::Error for GrappleError<'a> {}
+
+impl<'a> From<binmarshal::MarshalError> for GrappleError<'a> {
+  fn from(value: binmarshal::MarshalError) -> Self {
+    Self::Generic(AsymmetricCow(Cow::Owned(alloc::format!("Marshal Error: {:?}", value))))
   }
 }
 
@@ -1,7 +1,7 @@
 use binmarshal::{Marshal, Demarshal, MarshalUpdate, Payload, AsymmetricCow};
 use bounded_static::ToStatic;
 
-use crate::Validate;
+use crate::{Validate, ValidateUtf8, macros::no_strings};
 
 use super::{errors::GrappleResult, GrappleMessageId, Request};
 
@@ -68,4 +68,18 @@ impl<'a> Validate for GrappleFirmwareMessage<'a> {
   fn validate(&self) -> GrappleResult<()> {
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+no_strings!(UpdatePartV2Payload<'_>, FlashParameters);
+
+impl<'a> ValidateUtf8 for GrappleFirmwareMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      GrappleFirmwareMessage::UpdatePartV2(req) => req.validate_utf8(),
+      GrappleFirmwareMessage::GetFlashParameters(req) => req.validate_utf8(),
+      #[cfg(feature = "firmware_update_v1")]
+      GrappleFirmwareMessage::UpdatePart(_) | GrappleFirmwareMessage::UpdatePartAck => Ok(()),
+      GrappleFirmwareMessage::StartFieldUpgrade { .. } | GrappleFirmwareMessage::UpdateDone => Ok(())
+    }
+  }
+}
@@ -1,6 +1,8 @@
 use binmarshal::{Demarshal, Marshal, MarshalUpdate};
 use bounded_static::ToStatic;
 
+use crate::ValidateUtf8;
+
 use super::{encapsulation::BridgeMessages, GrappleMessageId};
 
 #[derive(Clone, Debug, PartialEq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
@@ -15,4 +17,12 @@ pub enum FlexiCANMessage<'a> {
     #[cfg_attr(feature = "serde", serde(borrow))]
     BridgeMessages<'a>
   ),
-}
\ No newline at end of file
+}
+
+impl<'a> ValidateUtf8 for FlexiCANMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      FlexiCANMessage::Bridge(bridge) => bridge.validate_utf8(),
+    }
+  }
+}
@@ -1,14 +1,64 @@
 use core::ops::{Index, RangeFull};
 
-use alloc::borrow::Cow;
-use binmarshal::{BitView, BufferBitWriter, BitWriter, Payload, Marshal, Demarshal, MarshalUpdate, AsymmetricCow};
-use bounded_static::ToStatic;
+use alloc::{borrow::Cow, collections::{BTreeMap, VecDeque}};
+use binmarshal::{BitView, BitWriter, Payload, Marshal, Demarshal, MarshalUpdate, AsymmetricCow, VecBitWriter};
+use bounded_static::{ToBoundedStatic, ToStatic};
 use smallvec::SmallVec;
 
-use crate::MessageId;
+use crate::{MessageId, ValidateUtf8, can::CanFrameFormat};
 
 use super::{GrappleMessageId, MaybeFragment, GrappleDeviceMessage, MANUFACTURER_GRAPPLE};
 
+// Fragmented messages come in two formats, distinguished by the ack flag, which is never otherwise
+// set on a fragment:
+//  - Standard: the fragment index is the 4-bit api_index, and the start fragment carries a
+//    3 byte header (api_class, api_index, total_len: u8). Limited to 255 bytes and 16 fragments.
+//  - Extended: every fragment begins with a big-endian u16 index, and the start fragment
+//    (index 0) follows it with a 4 byte header (api_class, api_index, total_len: u16 BE).
+// The transmitter only uses the extended format for messages that don't fit the standard one,
+// so devices that only understand the standard format are unaffected.
+//
+// In either format, the top bit of the start header's api_class may flag that the header is
+// followed by a big-endian CRC-16/CCITT-FALSE of the reassembled message. Receivers that predate
+// the CRC will misread these messages, so transmitters only add it once enabled with `set_crc`.
+//
+// A receiver may ask for lost fragments to be sent again with a NACK. A NACK is flagged like an
+// extended fragment, but with an api_index of 1, and has the device and fragment ID of the transfer
+// it refers to. Its payload is a list of big-endian u16 fragment indices. Like the CRC, receivers only
+// send NACKs once enabled with `set_nack`, since older receivers would take them for fragments.
+pub const MAX_STANDARD_FRAGMENTS: usize = 16;
+pub const MAX_STANDARD_LEN: usize = u8::MAX as usize;
+pub const MAX_EXTENDED_LEN: usize = u16::MAX as usize;
+
+const STANDARD_START_HEADER_LEN: usize = 3;
+const EXTENDED_START_HEADER_LEN: usize = 6;
+const EXTENDED_HEADER_LEN: usize = 2;
+const START_CRC_FLAG: u8 = 0x80;
+const CRC_LEN: usize = 2;
+// Only the low 4 bits of the api_class are free to carry the fragment ID
+const FRAGMENT_IDS: u8 = 16;
+const NACK_API_INDEX: u8 = 1;
+// NACKs are kept to classic CAN frames, so they fit on any bus
+pub const NACK_INDICES_PER_FRAME: usize = 4;
+// The most fragments a receiver asks for at a time for any one transfer
+const MAX_NACK_INDICES: usize = 32;
+// The transmitter never uses frames too small for a start header and one byte of the message, so
+// each fragment after the start carries at least this much of it
+const MIN_FRAGMENT_PAYLOAD: usize = STANDARD_START_HEADER_LEN + 1;
+// How many transfers a receiver holds at once by default, before it starts evicting the quietest
+pub const DEFAULT_MAX_TRANSFERS: usize = 64;
+
+// The most fragments a message of total_len bytes can be split into
+fn max_fragments(total_len: usize) -> usize {
+  total_len.div_ceil(MIN_FRAGMENT_PAYLOAD) + 1
+}
+
+fn crc16(data: &[u8]) -> u16 {
+  data.iter().fold(0xFFFF, |crc, &b| {
+    (0..8).fold(crc ^ ((b as u16) << 8), |crc, _| if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 })
+  })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -16,9 +66,14 @@ pub enum FragmentBody {
   Start {
     api_class: u8,
     api_index: u8,
-    total_len: u8,
+    total_len: u16,
+    crc: Option<u16>,
+  },
+  Fragment,
+  // Held inline, so reading a NACK never allocates
+  Nack {
+    missing: SmallVec<[u16; NACK_INDICES_PER_FRAME]>,
   },
-  Fragment
 }
 
 #[derive(Debug, Clone, PartialEq, ToStatic)]
@@ -26,22 +81,84 @@ pub enum FragmentBody {
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Fragment<'a> {
   fragment_id: u8,
-  index: u8,
+  #[cfg_attr(feature = "serde", serde(default))]
+  extended: bool,
+  index: u16,
   body: FragmentBody,
   payload: AsymmetricCow<'a, Payload>,
 }
 
+impl<'a> Fragment<'a> {
+  // The fragment ID, and the index in the standard format, only have 4 bits on the wire, so larger
+  // values fail to write rather than wrap. A Start body belongs at index 0.
+  pub fn new(fragment_id: u8, extended: bool, index: u16, body: FragmentBody, payload: &'a [u8]) -> Self {
+    Self { fragment_id, extended, index, body, payload: Cow::Borrowed(Into::<&Payload>::into(payload)).into() }
+  }
+
+  pub fn fragment_id(&self) -> u8 {
+    self.fragment_id
+  }
+
+  pub fn extended(&self) -> bool {
+    self.extended
+  }
+
+  pub fn index(&self) -> u16 {
+    self.index
+  }
+
+  pub fn body(&self) -> &FragmentBody {
+    &self.body
+  }
+
+  pub fn payload(&self) -> &[u8] {
+    &self.payload[..]
+  }
+}
+
 impl<'a> Marshal<GrappleMessageId> for Fragment<'a> {
   fn write<W: BitWriter>(&self, writer: &mut W, _ctx: GrappleMessageId) -> Result<(), binmarshal::MarshalError> {
-    match self.body {
-      FragmentBody::Start { api_class, api_index, total_len } => {
-        let buf = writer.reserve_and_advance_aligned_slice(3)?;
+    if self.fragment_id >= FRAGMENT_IDS || (!self.extended && self.index as usize >= MAX_STANDARD_FRAGMENTS) {
+      return Err(binmarshal::MarshalError::IllegalValue { byte_offset: writer.slice().len(), bit_offset: writer.bit_offset() });
+    }
+
+    if let FragmentBody::Nack { missing } = &self.body {
+      if missing.len() > NACK_INDICES_PER_FRAME {
+        return Err(binmarshal::MarshalError::CoercionError);
+      }
+      for index in missing {
+        writer.reserve_and_advance_aligned_slice(2)?.copy_from_slice(&index.to_be_bytes());
+      }
+      return Ok(());
+    }
+
+    if self.extended {
+      writer.reserve_and_advance_aligned_slice(EXTENDED_HEADER_LEN)?.copy_from_slice(&self.index.to_be_bytes());
+    }
+
+    if let FragmentBody::Start { api_class, api_index, total_len, crc } = self.body {
+      let api_class = match crc {
+        Some(_) => api_class | START_CRC_FLAG,
+        None => api_class,
+      };
+
+      if self.extended {
+        let buf = writer.reserve_and_advance_aligned_slice(4)?;
+        buf[0] = api_class;
+        buf[1] = api_index;
+        buf[2..4].copy_from_slice(&total_len.to_be_bytes());
+      } else {
+        let total_len = u8::try_from(total_len).map_err(|_| binmarshal::MarshalError::CoercionError)?;
+        let buf = writer.reserve_and_advance_aligned_slice(STANDARD_START_HEADER_LEN)?;
         buf[0] = api_class;
         buf[1] = api_index;
         buf[2] = total_len;
-      },
-      FragmentBody::Fragment => (),
-    };
+      }
+
+      if let Some(crc) = crc {
+        writer.reserve_and_advance_aligned_slice(CRC_LEN)?.copy_from_slice(&crc.to_be_bytes());
+      }
+    }
 
     self.payload.write(writer, ())?;
 
@@ -49,26 +166,76 @@ impl<'a> Marshal<GrappleMessageId> for Fragment<'a> {
   }
 }
 
+fn read_start_crc(view: &mut BitView<'_>, api_class: u8) -> Result<Option<u16>, binmarshal::MarshalError> {
+  if api_class & START_CRC_FLAG != 0 {
+    let crc = view.take_aligned_slice(CRC_LEN)?;
+    Ok(Some(u16::from_be_bytes([crc[0], crc[1]])))
+  } else {
+    Ok(None)
+  }
+}
+
 impl<'dm> Demarshal<'dm, GrappleMessageId> for Fragment<'dm> {
   fn read(view: &mut BitView<'dm>, ctx: GrappleMessageId) -> Result<Self, binmarshal::MarshalError> {
+    if ctx.ack_flag && ctx.api_index == NACK_API_INDEX {
+      let payload: AsymmetricCow<'dm, Payload> = Demarshal::read(view, ())?;
+      if !payload.len().is_multiple_of(2) || payload.len() > NACK_INDICES_PER_FRAME * 2 {
+        return Err(binmarshal::MarshalError::CoercionError);
+      }
+
+      return Ok(Fragment::new(
+        ctx.api_class,
+        true,
+        0,
+        FragmentBody::Nack { missing: payload[..].chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]])).collect() },
+        &[],
+      ));
+    }
+
+    if ctx.ack_flag {
+      let index = view.take_aligned_slice(EXTENDED_HEADER_LEN)?;
+      let index = u16::from_be_bytes([index[0], index[1]]);
+
+      let body = match index {
+        0 => {
+          let header = view.take_aligned_slice(4)?;
+          let (api_class, api_index, total_len) = (header[0], header[1], u16::from_be_bytes([header[2], header[3]]));
+          let crc = read_start_crc(view, api_class)?;
+          FragmentBody::Start { api_class: api_class & !START_CRC_FLAG, api_index, total_len, crc }
+        },
+        _ => FragmentBody::Fragment
+      };
+
+      return Ok(Fragment {
+        fragment_id: ctx.api_class,
+        extended: true,
+        index,
+        body,
+        payload: Demarshal::read(view, ())?
+      });
+    }
+
     match ctx.api_index {
       0b0 => {
-        let header = view.take_aligned_slice(3)?;
+        let header = view.take_aligned_slice(STANDARD_START_HEADER_LEN)?;
         let api_class = header[0];
         let api_index = header[1];
         let total_len = header[2];
+        let crc = read_start_crc(view, api_class)?;
 
         Ok(Fragment {
           fragment_id: ctx.api_class,
+          extended: false,
           index: 0,
-          body: FragmentBody::Start { api_class, api_index, total_len },
+          body: FragmentBody::Start { api_class: api_class & !START_CRC_FLAG, api_index, total_len: total_len as u16, crc },
           payload: Demarshal::read(view, ())?
         })
       },
       index => {
         Ok(Fragment {
           fragment_id: ctx.api_class,
-          index,
+          extended: false,
+          index: index as u16,
           body: FragmentBody::Fragment,
           payload: Demarshal::read(view, ())?
         })
@@ -80,24 +247,233 @@ impl<'dm> Demarshal<'dm, GrappleMessageId> for Fragment<'dm> {
 impl<'a> MarshalUpdate<GrappleMessageId> for Fragment<'a> {
   fn update(&mut self, ctx: &mut GrappleMessageId) {
     ctx.fragment_flag = true;
-    ctx.ack_flag = false;
+    ctx.ack_flag = self.extended;
     ctx.api_class = self.fragment_id;
-    ctx.api_index = self.index;
+    ctx.api_index = match (&self.body, self.extended) {
+      (FragmentBody::Nack { .. }, _) => NACK_API_INDEX,
+      (_, true) => 0,
+      (_, false) => self.index as u8,
+    };
   }
 }
 
 #[derive(PartialEq, Eq)]
 pub struct FragmentSetKey {
+  channel: u8,
   device_id: u8,
   device_type: u8,
   fragment_idx: u8,
+  extended: bool,
+}
+
+impl FragmentSetKey {
+  fn device(&self) -> FragmentDeviceKey {
+    FragmentDeviceKey { channel: self.channel, device_type: self.device_type, device_id: self.device_id }
+  }
 }
 
 pub struct Fragments {
   key: FragmentSetKey,
-  header: Option<(/* api class */ u8, /* api index */ u8, /* total len */ u8)>,
-  data: smallvec::SmallVec<[Option<smallvec::SmallVec<[u8; 8]>>; 8]>,
+  header: Option<(/* api class */ u8, /* api index */ u8, /* total len */ u16, /* crc */ Option<u16>)>,
+  // Sorted by fragment index
+  data: smallvec::SmallVec<[(u16, smallvec::SmallVec<[u8; 8]>); 8]>,
+  n_bytes: usize,
   last_seen: i64,
+  nacked_at: Option<i64>,
+}
+
+impl Fragments {
+  // The indices of the fragments we're still waiting on, as best we can tell. Without the start
+  // fragment we don't know how long the message is. With it we do, and since a fragmented message's
+  // start always fills its frame, we also know how much every fragment but the last carries.
+  fn missing(&self) -> alloc::vec::Vec<u16> {
+    let mut missing = alloc::vec::Vec::new();
+    let mut next = 0u16;
+    for (index, _) in self.data.iter() {
+      missing.extend(next..*index);
+      next = index.saturating_add(1);
+    }
+
+    if let (Some((_, _, total_len, crc)), Some((0, start))) = (self.header, self.data.first()) {
+      let missing_bytes = (total_len as usize).saturating_sub(self.n_bytes);
+      let (start_header_len, header_len) = match self.key.extended {
+        true => (EXTENDED_START_HEADER_LEN, EXTENDED_HEADER_LEN),
+        false => (STANDARD_START_HEADER_LEN, 0),
+      };
+      let crc_len = if crc.is_some() { CRC_LEN } else { 0 };
+      let fragment_len = (start.len() + start_header_len + crc_len).saturating_sub(header_len);
+      let trailing = match fragment_len {
+        0 => (missing_bytes > 0) as usize,
+        len => missing_bytes.saturating_sub(missing.iter().filter(|i| **i != 0).count() * len).div_ceil(len),
+      };
+      missing.extend((next..=u16::MAX).take(trailing));
+    }
+
+    missing.truncate(MAX_NACK_INDICES);
+    missing
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DefragmentError {
+  Marshal(binmarshal::MarshalError),
+  // Enough data arrived to complete the message, but not as an unbroken run of fragments from the
+  // start fragment. Usually fragments left over from an older transfer with the same ID.
+  Discontiguous,
+  // The reassembled message doesn't match the CRC in its start fragment
+  CrcMismatch { expected: u16, actual: u16 },
+}
+
+impl From<binmarshal::MarshalError> for DefragmentError {
+  fn from(value: binmarshal::MarshalError) -> Self {
+    DefragmentError::Marshal(value)
+  }
+}
+
+impl core::fmt::Display for DefragmentError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      DefragmentError::Marshal(e) => write!(f, "Marshal Error: {:?}", e),
+      DefragmentError::Discontiguous => write!(f, "Fragments are not contiguous"),
+      DefragmentError::CrcMismatch { expected, actual } => write!(f, "CRC Mismatch: expected {:04x}, got {:04x}", expected, actual),
+    }
+  }
+}
+
+impl core::error::Error for DefragmentError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+  Marshal(binmarshal::MarshalError),
+  // Every fragment ID for the device is held by a transfer from `fragment` that hasn't been released
+  IdsExhausted,
+}
+
+impl From<binmarshal::MarshalError> for FragmentError {
+  fn from(value: binmarshal::MarshalError) -> Self {
+    FragmentError::Marshal(value)
+  }
+}
+
+impl core::fmt::Display for FragmentError {
+  fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    match self {
+      FragmentError::Marshal(e) => write!(f, "Marshal Error: {:?}", e),
+      FragmentError::IdsExhausted => write!(f, "No free fragment IDs, are transfers being released?"),
+    }
+  }
+}
+
+impl core::error::Error for FragmentError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FragmentDeviceKey {
+  // The bus the device is on: the one a receiver heard it on, or the one a transmitter sends to it on
+  pub channel: u8,
+  pub device_type: u8,
+  pub device_id: u8,
+}
+
+// Every transfer that's started is eventually completed, failed, aged off or abandoned, so
+// started = completed + decode_failures + aged_off + abandoned + in_flight
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FragmentRxStats {
+  pub started: u64,
+  pub completed: u64,
+  // Received every fragment, but was discontiguous, failed its CRC, or failed to decode
+  pub decode_failures: u64,
+  // Dropped after age_off passed without another fragment
+  pub aged_off: u64,
+  // Dropped for a new transfer with the same fragment ID, or to make room for another transfer
+  pub abandoned: u64,
+  pub in_flight: u64,
+  pub duplicates: u64,
+  // NACK frames sent
+  pub nacks: u64,
+}
+
+impl FragmentRxStats {
+  const ZERO: Self = Self { started: 0, completed: 0, decode_failures: 0, aged_off: 0, abandoned: 0, in_flight: 0, duplicates: 0, nacks: 0 };
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FragmentTxStats {
+  pub messages: u64,
+  // Messages that had to be split into fragments
+  pub fragmented: u64,
+  pub frames: u64,
+  pub failures: u64,
+  // Frames sent again in answer to a NACK
+  pub resent: u64,
+}
+
+// Fragment IDs for transfers to one device. IDs only have to be unique per device, so each device
+// cycles through its own, skipping any still in use. An ID is in use until the caller has finished
+// sending its transfer, and for as long as the transfer is retained for retransmission. Each
+// allocation of an ID gets a new generation, so releasing a transfer late can't free the ID from
+// under a newer one.
+#[derive(Default)]
+struct FragmentIds {
+  last: u8,
+  sending: u16,
+  retained: u16,
+  generations: [u32; FRAGMENT_IDS as usize],
+}
+
+impl FragmentIds {
+  fn allocate(&mut self) -> Option<(u8, u32)> {
+    let id = (1..=FRAGMENT_IDS)
+      .map(|i| (self.last + i) % FRAGMENT_IDS)
+      .find(|id| (self.sending | self.retained) & (1 << id) == 0)?;
+    self.last = id;
+    self.sending |= 1 << id;
+    let generation = &mut self.generations[id as usize];
+    *generation = generation.wrapping_add(1);
+    Some((id, *generation))
+  }
+
+  fn sent(&mut self, id: u8, generation: u32) {
+    if self.generations[id as usize] == generation {
+      self.sending &= !(1 << id);
+    }
+  }
+
+  fn retain(&mut self, id: u8) {
+    self.retained |= 1 << id;
+  }
+
+  fn drop_retained(&mut self, id: u8) {
+    self.retained &= !(1 << id);
+  }
+}
+
+#[derive(Default)]
+struct RxCounters {
+  total: FragmentRxStats,
+  devices: BTreeMap<FragmentDeviceKey, FragmentRxStats>,
+}
+
+impl RxCounters {
+  fn record(&mut self, device: FragmentDeviceKey, f: impl Fn(&mut FragmentRxStats)) {
+    f(&mut self.total);
+    f(self.devices.entry(device).or_default());
+  }
+}
+
+// A reassembled message that owns its data, so it can be sent to another thread or kept around
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReassembledMessage {
+  pub channel: u8,
+  pub id: GrappleMessageId,
+  // When the frame that completed the message was received
+  pub timestamp: i64,
+  pub message: GrappleDeviceMessage<'static>,
 }
 
 pub struct FragmentReassembler {
@@ -107,7 +483,16 @@ pub struct FragmentReassembler {
 
 impl FragmentReassembler {
   pub fn new(age_off: i64, max_fragment_size: usize) -> Self {
-    Self { rx: FragmentReassemblerRx { messages: alloc::vec![], age_off }, tx: FragmentReassemblerTx { max_fragment_size, fragment_id: 0 } }
+    Self {
+      rx: FragmentReassemblerRx { messages: alloc::vec![], age_off, max_transfers: DEFAULT_MAX_TRANSFERS, nack_after: None, counters: RxCounters::default() },
+      tx: FragmentReassemblerTx {
+        max_fragment_size, fragment_ids: BTreeMap::new(), crc: false, retain: 0, retained: VecDeque::new(), stats: FragmentTxStats::default()
+      }
+    }
+  }
+
+  pub fn new_for_format(age_off: i64, format: CanFrameFormat) -> Self {
+    Self::new(age_off, format.max_data_len())
   }
 
   pub fn split(self) -> (FragmentReassemblerRx, FragmentReassemblerTx) {
@@ -118,30 +503,182 @@ impl FragmentReassembler {
 pub struct FragmentReassemblerRx {
   messages: alloc::vec::Vec<Fragments>,
   age_off: i64,
+  max_transfers: usize,
+  nack_after: Option<i64>,
+  counters: RxCounters,
 }
 
 pub struct FragmentReassemblerTx {
   max_fragment_size: usize,
-  fragment_id: u8,
+  fragment_ids: BTreeMap<FragmentDeviceKey, FragmentIds>,
+  crc: bool,
+  retain: usize,
+  // Oldest first. These keep their fragment IDs in flight until they're dropped.
+  retained: VecDeque<(FragmentDeviceKey, FragmentFrames)>,
+  stats: FragmentTxStats,
+}
+
+// Checks the CRC of a reassembled message and decodes it
+fn read_reassembled<'a>(id: &MessageId, api_class: u8, api_index: u8, crc: Option<u16>, data: &'a [u8]) -> Result<(GrappleMessageId, GrappleDeviceMessage<'a>), DefragmentError> {
+  if let Some(expected) = crc {
+    let actual = crc16(data);
+    if expected != actual {
+      return Err(DefragmentError::CrcMismatch { expected, actual });
+    }
+  }
+
+  let mid = MessageId {
+    device_type: id.device_type,
+    manufacturer: MANUFACTURER_GRAPPLE,
+    api_class,
+    api_index,
+    device_id: id.device_id,
+  };
+  let gmsgid: GrappleMessageId = mid.into();
+  let msg = GrappleDeviceMessage::read(&mut BitView::new(data), gmsgid.clone())?;
+  msg.validate_utf8()?;
+  Ok((gmsgid, msg))
 }
 
 impl FragmentReassemblerRx {
-  pub fn defragment<'a, E: Extend<u8> + Index<RangeFull, Output = [u8]>>(&mut self, now: i64, id: &MessageId, message: MaybeFragment<'a>, storage: &'a mut E) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'a>)>, binmarshal::MarshalError> {
-    self.messages.retain(|frags| (now - frags.last_seen) <= self.age_off);
+  // Drop transfers that haven't seen a fragment within age_off. defragment does this on every call,
+  // so only call this to bring in_flight up to date when the bus is quiet.
+  pub fn prune(&mut self, now: i64) {
+    let age_off = self.age_off;
+    let counters = &mut self.counters;
+    self.messages.retain(|frags| {
+      let keep = (now - frags.last_seen) <= age_off;
+      if !keep {
+        counters.record(frags.key.device(), |s| s.aged_off += 1);
+      }
+      keep
+    });
+  }
+
+  pub fn stats(&self) -> FragmentRxStats {
+    FragmentRxStats { in_flight: self.messages.len() as u64, ..self.counters.total.clone() }
+  }
+
+  pub fn device_stats(&self) -> BTreeMap<FragmentDeviceKey, FragmentRxStats> {
+    let mut stats = self.counters.devices.clone();
+    for frags in self.messages.iter() {
+      stats.entry(frags.key.device()).or_default().in_flight += 1;
+    }
+    stats
+  }
+
+  pub fn reset_stats(&mut self) {
+    self.counters = RxCounters::default();
+  }
+
+  // Hold at most `transfers` transfers at once. Starting another evicts the one that has been quiet
+  // the longest, which counts as abandoned. DEFAULT_MAX_TRANSFERS by default.
+  pub fn set_max_transfers(&mut self, transfers: usize) {
+    self.max_transfers = transfers.max(1);
+    while self.messages.len() > self.max_transfers {
+      self.evict_quietest();
+    }
+  }
+
+  fn evict_quietest(&mut self) {
+    if let Some(i) = self.messages.iter().enumerate().min_by_key(|(_, x)| x.last_seen).map(|(i, _)| i) {
+      let frags = self.messages.remove(i);
+      self.counters.record(frags.key.device(), |s| s.abandoned += 1);
+    }
+  }
+
+  // NACK transfers that have gone `after` without a fragment, so the sender resends what's missing.
+  // Only enable this if every device on the bus supports NACKs. Off (None) by default.
+  pub fn set_nack(&mut self, after: Option<i64>) {
+    self.nack_after = after;
+  }
+
+  // Send NACKs for any transfers that are due one. Call this regularly, e.g. whenever the bus is idle.
+  pub fn poll_nacks<Consumer: FnMut(MessageId, &[u8])>(&mut self, now: i64, consumer: &mut Consumer) {
+    self.poll_nacks_on_channel(0, now, consumer)
+  }
+
+  // Send the NACKs that are due for transfers on one channel, to be sent on that channel's bus
+  pub fn poll_nacks_on_channel<Consumer: FnMut(MessageId, &[u8])>(&mut self, channel: u8, now: i64, consumer: &mut Consumer) {
+    let Some(after) = self.nack_after else { return };
+    self.prune(now);
+
+    for frags in self.messages.iter_mut().filter(|x| x.key.channel == channel) {
+      let quiet_since = frags.nacked_at.map_or(frags.last_seen, |t| t.max(frags.last_seen));
+      if now - quiet_since < after {
+        continue;
+      }
+
+      let missing = frags.missing();
+      if missing.is_empty() {
+        continue;
+      }
+      frags.nacked_at = Some(now);
+
+      for chunk in missing.chunks(NACK_INDICES_PER_FRAME) {
+        let mut nack = Fragment::new(frags.key.fragment_idx, true, 0, FragmentBody::Nack { missing: SmallVec::from_slice(chunk) }, &[]);
+        let mut id = GrappleMessageId::new(frags.key.device_id);
+        id.device_type = frags.key.device_type;
+        nack.update(&mut id);
+
+        let mut writer = VecBitWriter::new();
+        if nack.write(&mut writer, id.clone()).is_ok() {
+          consumer(id.into(), writer.slice());
+          self.counters.record(frags.key.device(), |s| s.nacks += 1);
+        }
+      }
+    }
+  }
+
+  // Like defragment, but without caller-provided storage. The message is copied out of the frames,
+  // so the result doesn't borrow anything.
+  pub fn defragment_owned(&mut self, now: i64, id: &MessageId, message: MaybeFragment) -> Result<Option<ReassembledMessage>, DefragmentError> {
+    self.defragment_owned_on_channel(0, now, id, message)
+  }
+
+  pub fn defragment_owned_on_channel(&mut self, channel: u8, now: i64, id: &MessageId, message: MaybeFragment) -> Result<Option<ReassembledMessage>, DefragmentError> {
+    let mut storage = alloc::vec::Vec::new();
+    Ok(self.defragment_on_channel(channel, now, id, message, &mut storage)?.map(|(id, message)| ReassembledMessage {
+      channel, id, timestamp: now, message: message.to_static()
+    }))
+  }
+
+  pub fn defragment<'a, E: Extend<u8> + Index<RangeFull, Output = [u8]>>(&mut self, now: i64, id: &MessageId, message: MaybeFragment<'a>, storage: &'a mut E) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'a>)>, DefragmentError> {
+    self.defragment_on_channel(0, now, id, message, storage)
+  }
+
+  // When listening on several buses, give each a channel of its own (e.g. the channel of a FlexiCAN
+  // EncapsulatedMesssage), so devices with the same ID on different buses don't corrupt each other's
+  // transfers. defragment uses channel 0.
+  pub fn defragment_on_channel<'a, E: Extend<u8> + Index<RangeFull, Output = [u8]>>(&mut self, channel: u8, now: i64, id: &MessageId, message: MaybeFragment<'a>, storage: &'a mut E) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'a>)>, DefragmentError> {
+    self.prune(now);
 
     match message {
+      // NACKs are for the transmitter
+      MaybeFragment::Fragment(Fragment { body: FragmentBody::Nack { .. }, .. }) => Ok(None),
+      // Only the start may be empty, and only in the extended format. An empty fragment carries
+      // nothing, so the transmitter never sends one.
+      MaybeFragment::Fragment(Fragment { body: FragmentBody::Fragment, ref payload, .. }) if payload.is_empty() => {
+        Err(binmarshal::MarshalError::CoercionError.into())
+      },
       MaybeFragment::Fragment(frag) => {
         let key = FragmentSetKey {
+          channel,
           device_id: id.device_id,
           device_type: id.device_type,
           fragment_idx: frag.fragment_id,
+          extended: frag.extended,
         };
 
         // Find or insert
         let idx = match self.messages.iter().position(|x| x.key == key) {
           Some(idx) => idx,
           None => {
-            self.messages.push(Fragments { key, header: None, data: smallvec::SmallVec::with_capacity(2), last_seen: now });
+            if self.messages.len() >= self.max_transfers {
+              self.evict_quietest();
+            }
+            self.counters.record(key.device(), |s| s.started += 1);
+            self.messages.push(Fragments { key, header: None, data: smallvec::SmallVec::new(), n_bytes: 0, last_seen: now, nacked_at: None });
             let n = self.messages.len();
             n - 1
           }
@@ -149,86 +686,373 @@ impl FragmentReassemblerRx {
 
         let fragments = self.messages.get_mut(idx).unwrap();
         fragments.last_seen = now;
+        let device = fragments.key.device();
 
-        let seq = frag.index as usize;
-        if seq >= fragments.data.len() {
-          fragments.data.resize(seq as usize + 1, None);
+        match frag.body {
+          FragmentBody::Start { api_class, api_index, total_len, crc } => {
+            // Another start fragment means the sender has begun a new transfer with this ID, so
+            // anything we're still holding from the last one is stale.
+            if fragments.header.is_some() {
+              fragments.data.clear();
+              fragments.n_bytes = 0;
+              self.counters.record(device, |s| { s.abandoned += 1; s.started += 1 });
+            }
+            fragments.header = Some((api_class, api_index, total_len, crc));
+          },
+          FragmentBody::Fragment | FragmentBody::Nack { .. } => {},
         }
 
-        match frag.body {
-          FragmentBody::Start { api_class, api_index, total_len } => {
-            fragments.header = Some((api_class, api_index, total_len));
+        let data = smallvec::SmallVec::from_slice(&frag.payload[..]);
+        fragments.n_bytes += data.len();
+        match fragments.data.binary_search_by_key(&frag.index, |(i, _)| *i) {
+          Ok(i) => {
+            self.counters.record(device, |s| s.duplicates += 1);
+            fragments.n_bytes -= fragments.data[i].1.len();
+            fragments.data[i].1 = data;
           },
-          FragmentBody::Fragment => {},
+          Err(i) => fragments.data.insert(i, (frag.index, data)),
         }
 
-        fragments.data[seq] = {
-          let mut data = smallvec::smallvec![0u8; frag.payload.len()];
-          data.copy_from_slice(&frag.payload[..]);
-          Some(data)
+        // No complete message can be larger than this, or made of more fragments than its length
+        // allows, so don't let a stream of bogus fragments hold onto more memory than that. Once
+        // there's enough data to complete the message, it's checked below instead.
+        let (max_len, complete) = match fragments.header {
+          Some((_, _, total_len, _)) => (total_len as usize, fragments.n_bytes >= total_len as usize),
+          None => (MAX_EXTENDED_LEN, false),
         };
+        if fragments.n_bytes > MAX_EXTENDED_LEN + CanFrameFormat::Fd.max_data_len() || (!complete && fragments.data.len() > max_fragments(max_len)) {
+          self.counters.record(device, |s| s.decode_failures += 1);
+          self.messages.remove(idx);
+          return Err(binmarshal::MarshalError::BufferTooSmall.into());
+        }
 
         let ret = match fragments.header {
-          Some((api_class, api_index, total_len)) => {
-            let n_bytes_seen = fragments.data.iter().fold(0usize, |a, b| a + b.as_ref().map(|x| x.len()).unwrap_or(0usize));
-
-            if n_bytes_seen >= total_len as usize {
-              // Fragment is complete - reassemble it
-              for el in &fragments.data {
-                if let Some(el) = el.as_ref() {
-                  storage.extend(el.iter().cloned());
-                }
+          Some((api_class, api_index, total_len, crc)) if fragments.n_bytes >= total_len as usize => {
+            // The message has to be made up of an unbroken run of fragments from the start
+            // fragment, and nothing can follow the fragment that completes it.
+            let mut n_fragments = 0;
+            let mut n_bytes = 0;
+            for (i, (index, data)) in fragments.data.iter().enumerate() {
+              if *index as usize != i {
+                break;
               }
-
-              let mut view = BitView::new(&storage[..]);
-              let mid = MessageId {
-                device_type: id.device_type,
-                manufacturer: MANUFACTURER_GRAPPLE,
-                api_class,
-                api_index,
-                device_id: id.device_id,
-              };
-              let gmsgid: GrappleMessageId = mid.clone().into();
-              let msg = GrappleDeviceMessage::read(&mut view, gmsgid.clone());
-
-              match msg {
-                Ok(msg) => Ok(Some((gmsgid, msg))),
-                Err(e) => Err(e),
+              n_fragments += 1;
+              n_bytes += data.len();
+              if n_bytes >= total_len as usize {
+                break;
               }
+            }
+
+            if n_bytes < total_len as usize || n_fragments != fragments.data.len() {
+              Err(DefragmentError::Discontiguous)
             } else {
-              Ok(None)
+              // Fragment is complete - reassemble it. CAN FD transports may pad the last frame
+              // up to the next DLC step, so only take total_len bytes.
+              storage.extend(
+                fragments.data.iter()
+                  .flat_map(|(_, x)| x.iter().cloned())
+                  .take(total_len as usize)
+              );
+
+              read_reassembled(id, api_class, api_index, crc, &storage[..]).map(Some)
             }
           },
-          None => Ok(None)
+          _ => Ok(None)
         };
 
         match ret {
           Ok(Some(v)) => {
+            self.counters.record(device, |s| s.completed += 1);
             self.messages.remove(idx);
             Ok(Some(v))
           },
           Ok(None) => Ok(None),
           Err(e) => {
+            self.counters.record(device, |s| s.decode_failures += 1);
             self.messages.remove(idx);
             Err(e)
           },
         }
       },
       MaybeFragment::Message(msg) => {
+        msg.validate_utf8()?;
         Ok(Some((GrappleMessageId::from(id.clone()), msg)))
       },
     }
   }
 }
 
+struct FixedTransfer<const MAX_LEN: usize> {
+  busy: bool,
+  key: FragmentSetKey,
+  header: (/* api class */ u8, /* api index */ u8, /* total len */ u16, /* crc */ Option<u16>),
+  next_index: u16,
+  len: usize,
+  data: [u8; MAX_LEN],
+  last_seen: i64,
+}
+
+impl<const MAX_LEN: usize> FixedTransfer<MAX_LEN> {
+  const EMPTY: Self = Self {
+    busy: false,
+    key: FragmentSetKey { channel: 0, device_id: 0, device_type: 0, fragment_idx: 0, extended: false },
+    header: (0, 0, 0, None),
+    next_index: 0,
+    len: 0,
+    data: [0; MAX_LEN],
+    last_seen: 0,
+  };
+}
+
+// A reassembler that never allocates, for firmware. It tracks up to TRANSFERS fragmented messages
+// at once, each at most MAX_LEN bytes long, and reads the same wire format as FragmentReassemblerRx.
+//
+// Unlike FragmentReassemblerRx, fragments must arrive in order, starting with the start fragment.
+// Fragments for a transfer we haven't seen start are ignored, a repeated fragment is ignored, and a
+// skipped fragment fails the transfer with DefragmentError::Discontiguous. When every slot is busy,
+// a new start fragment evicts the transfer that has gone the longest without receiving a fragment.
+// Stats are only kept in total, not per device.
+pub struct FixedFragmentReassemblerRx<const TRANSFERS: usize, const MAX_LEN: usize> {
+  transfers: [FixedTransfer<MAX_LEN>; TRANSFERS],
+  age_off: i64,
+  stats: FragmentRxStats,
+}
+
+impl<const TRANSFERS: usize, const MAX_LEN: usize> FixedFragmentReassemblerRx<TRANSFERS, MAX_LEN> {
+  pub const fn new(age_off: i64) -> Self {
+    Self { transfers: [FixedTransfer::EMPTY; TRANSFERS], age_off, stats: FragmentRxStats::ZERO }
+  }
+
+  pub fn prune(&mut self, now: i64) {
+    for transfer in self.transfers.iter_mut() {
+      if transfer.busy && (now - transfer.last_seen) > self.age_off {
+        transfer.busy = false;
+        self.stats.aged_off += 1;
+      }
+    }
+  }
+
+  pub fn stats(&self) -> FragmentRxStats {
+    FragmentRxStats { in_flight: self.transfers.iter().filter(|x| x.busy).count() as u64, ..self.stats.clone() }
+  }
+
+  pub fn reset_stats(&mut self) {
+    self.stats = FragmentRxStats::ZERO;
+  }
+
+  pub fn defragment<'a>(&'a mut self, now: i64, id: &MessageId, message: MaybeFragment<'a>) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'a>)>, DefragmentError> {
+    self.defragment_on_channel(0, now, id, message)
+  }
+
+  // See FragmentReassemblerRx::defragment_on_channel
+  pub fn defragment_on_channel<'a>(&'a mut self, channel: u8, now: i64, id: &MessageId, message: MaybeFragment<'a>) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'a>)>, DefragmentError> {
+    self.prune(now);
+
+    let frag = match message {
+      MaybeFragment::Fragment(Fragment { body: FragmentBody::Nack { .. }, .. }) => return Ok(None),
+      MaybeFragment::Fragment(frag) => frag,
+      MaybeFragment::Message(msg) => {
+        msg.validate_utf8()?;
+        return Ok(Some((GrappleMessageId::from(*id), msg)));
+      },
+    };
+
+    let key = FragmentSetKey {
+      channel,
+      device_id: id.device_id,
+      device_type: id.device_type,
+      fragment_idx: frag.fragment_id,
+      extended: frag.extended,
+    };
+    let existing = self.transfers.iter().position(|x| x.busy && x.key == key);
+
+    let idx = match frag.body {
+      FragmentBody::Start { api_class, api_index, total_len, crc } => {
+        if total_len as usize > MAX_LEN {
+          if let Some(idx) = existing {
+            self.transfers[idx].busy = false;
+            self.stats.abandoned += 1;
+          }
+          self.stats.started += 1;
+          self.stats.decode_failures += 1;
+          return Err(binmarshal::MarshalError::BufferTooSmall.into());
+        }
+
+        // Restart an existing transfer with this ID, then a free slot, then evict the stalest
+        let idx = existing
+          .or_else(|| self.transfers.iter().position(|x| !x.busy))
+          .or_else(|| self.transfers.iter().enumerate().min_by_key(|(_, x)| x.last_seen).map(|(i, _)| i));
+
+        let Some(idx) = idx else { return Ok(None) };
+        let transfer = &mut self.transfers[idx];
+        if transfer.busy {
+          self.stats.abandoned += 1;
+        }
+        self.stats.started += 1;
+        transfer.busy = true;
+        transfer.key = key;
+        transfer.header = (api_class, api_index, total_len, crc);
+        transfer.next_index = 0;
+        transfer.len = 0;
+        idx
+      },
+      FragmentBody::Fragment | FragmentBody::Nack { .. } => match existing {
+        Some(idx) => idx,
+        None => return Ok(None),
+      },
+    };
+
+    let transfer = &mut self.transfers[idx];
+    transfer.last_seen = now;
+
+    if frag.index < transfer.next_index {
+      self.stats.duplicates += 1;
+      return Ok(None);
+    } else if frag.index > transfer.next_index {
+      transfer.busy = false;
+      self.stats.decode_failures += 1;
+      return Err(DefragmentError::Discontiguous);
+    }
+
+    // CAN FD transports may pad the last frame up to the next DLC step, so only take total_len bytes.
+    let (api_class, api_index, total_len, crc) = transfer.header;
+    let n = frag.payload.len().min(total_len as usize - transfer.len);
+    transfer.data[transfer.len..transfer.len + n].copy_from_slice(&frag.payload[..n]);
+    transfer.len += n;
+    transfer.next_index = transfer.next_index.wrapping_add(1);
+
+    if transfer.len < total_len as usize {
+      return Ok(None);
+    }
+
+    transfer.busy = false;
+    let this: &'a mut Self = self;
+    let result = read_reassembled(id, api_class, api_index, crc, &this.transfers[idx].data[..total_len as usize]);
+    match result {
+      Ok(_) => this.stats.completed += 1,
+      Err(_) => this.stats.decode_failures += 1,
+    }
+    result.map(Some)
+  }
+}
+
 impl FragmentReassemblerTx {
   pub fn set_fragment_size(&mut self, size: usize) {
     self.max_fragment_size = size;
   }
 
-  pub fn maybe_fragment<Consumer: FnMut(MessageId, &[u8])>(&mut self, device_id: u8, mut message: GrappleDeviceMessage, consumer: &mut Consumer) -> Result<(), binmarshal::MarshalError> {
-    let mut payload = [0u8; 253];
-    let mut writer = BufferBitWriter::new(&mut payload);
+  pub fn set_frame_format(&mut self, format: CanFrameFormat) {
+    self.max_fragment_size = format.max_data_len();
+  }
+
+  // Add a CRC to the start fragment of every fragmented message. Only enable this if every
+  // receiver on the bus supports it.
+  pub fn set_crc(&mut self, enabled: bool) {
+    self.crc = enabled;
+  }
+
+  pub fn stats(&self) -> &FragmentTxStats {
+    &self.stats
+  }
+
+  pub fn reset_stats(&mut self) {
+    self.stats = FragmentTxStats::default();
+  }
+
+  // Hold on to the last `transfers` fragmented messages, so that any fragments a receiver NACKs can be
+  // resent. A retained transfer keeps its fragment ID, so retaining many transfers to one device
+  // limits how many can be queued for it. Off (0) by default.
+  pub fn set_retransmit(&mut self, transfers: usize) {
+    self.retain = transfers;
+    while self.retained.len() > transfers {
+      self.drop_oldest_retained(None);
+    }
+  }
+
+  // Resend the fragments asked for by a NACK. NACKs for transfers we no longer hold are ignored.
+  pub fn resend<Consumer: FnMut(MessageId, &[u8])>(&mut self, id: &MessageId, nack: &Fragment, consumer: &mut Consumer) {
+    self.resend_on_channel(0, id, nack, consumer)
+  }
+
+  // Resend the fragments asked for by a NACK heard on one channel, to be sent on that channel's bus
+  pub fn resend_on_channel<Consumer: FnMut(MessageId, &[u8])>(&mut self, channel: u8, id: &MessageId, nack: &Fragment, consumer: &mut Consumer) {
+    let FragmentBody::Nack { missing } = &nack.body else { return };
+    let dest = FragmentDeviceKey { channel, device_type: id.device_type, device_id: id.device_id };
+
+    let transfer = self.retained.iter()
+      .find(|(key, frames)| *key == dest && frames.transfer.as_ref().is_some_and(|t| t.fragment_id == nack.fragment_id));
+
+    if let Some((_, frames)) = transfer {
+      for index in missing {
+        if let Some((id, data)) = frames.frame(*index) {
+          consumer(id, &data[..]);
+          self.stats.resent += 1;
+        }
+      }
+    }
+  }
+
+  // Drop the oldest retained transfer, optionally only considering those to one device. Returns false
+  // if there was nothing to drop.
+  fn drop_oldest_retained(&mut self, dest: Option<FragmentDeviceKey>) -> bool {
+    let Some(i) = self.retained.iter().position(|(key, _)| dest.is_none_or(|d| d == *key)) else { return false };
+    if let Some((key, frames)) = self.retained.remove(i) {
+      if let (Some(ids), Some(transfer)) = (self.fragment_ids.get_mut(&key), frames.transfer) {
+        ids.drop_retained(transfer.fragment_id);
+      }
+    }
+    true
+  }
+
+  pub fn maybe_fragment<Consumer: FnMut(MessageId, &[u8])>(&mut self, device_id: u8, message: GrappleDeviceMessage, consumer: &mut Consumer) -> Result<(), FragmentError> {
+    self.maybe_fragment_on_channel(0, device_id, message, consumer)
+  }
+
+  pub fn maybe_fragment_on_channel<Consumer: FnMut(MessageId, &[u8])>(&mut self, channel: u8, device_id: u8, message: GrappleDeviceMessage, consumer: &mut Consumer) -> Result<(), FragmentError> {
+    let mut frames = self.fragment_on_channel(channel, device_id, message)?;
+    for (id, data) in &mut frames {
+      consumer(id, &data[..]);
+    }
+    self.release(&frames);
+    Ok(())
+  }
+
+  // Split a message into the frames that carry it, for callers that queue or pace frames themselves.
+  // Messages that fit in a single frame yield just that frame. The frames don't borrow the
+  // transmitter, so it can't tell when they've been sent: the transfer's fragment ID stays reserved
+  // until the frames are passed to `release`. A device can have at most 16 transfers in flight, after
+  // which this fails with IdsExhausted.
+  pub fn fragment(&mut self, device_id: u8, message: GrappleDeviceMessage) -> Result<FragmentFrames, FragmentError> {
+    self.fragment_on_channel(0, device_id, message)
+  }
+
+  // As `fragment`, for a device on one channel's bus. Each channel has its own fragment IDs and
+  // retained transfers, so only NACKs passed to `resend_on_channel` with the same channel resend them.
+  pub fn fragment_on_channel(&mut self, channel: u8, device_id: u8, message: GrappleDeviceMessage) -> Result<FragmentFrames, FragmentError> {
+    self.stats.messages += 1;
+    match self.plan_fragments(channel, device_id, message) {
+      Ok(frames) => {
+        self.stats.frames += frames.len() as u64;
+        Ok(frames)
+      },
+      Err(e) => {
+        self.stats.failures += 1;
+        Err(e)
+      }
+    }
+  }
+
+  // Free the fragment ID of a transfer from `fragment` once all of its frames have been sent, or
+  // abandoned. Releasing a transfer again, or after its ID has gone to a newer transfer, does nothing.
+  pub fn release(&mut self, frames: &FragmentFrames) {
+    let dest = FragmentDeviceKey { channel: frames.channel, device_type: frames.mid.device_type, device_id: frames.device_id };
+    if let (Some(ids), Some(transfer)) = (self.fragment_ids.get_mut(&dest), &frames.transfer) {
+      ids.sent(transfer.fragment_id, transfer.generation);
+    }
+  }
+
+  fn plan_fragments(&mut self, channel: u8, device_id: u8, mut message: GrappleDeviceMessage) -> Result<FragmentFrames, FragmentError> {
+    let mut writer = VecBitWriter::new();
     
     let mut id = GrappleMessageId::new(device_id);
     message.update(&mut id);
@@ -237,59 +1061,161 @@ impl FragmentReassemblerTx {
 
     message.write(&mut writer, id.clone())?;
 
-    let payload_slice = writer.slice();
-    
-    if payload_slice.len() <= self.max_fragment_size {
-      consumer(id.into(), payload_slice)
-    } else {
-      self.fragment_id = self.fragment_id.wrapping_add(1);
-
-      let first_size = self.max_fragment_size - 3;
-
-      let mut first = Fragment {
-        fragment_id: self.fragment_id,
-        index: 0,
-        body: FragmentBody::Start { api_class: mid.api_class, api_index: mid.api_index, total_len: payload_slice.len() as u8 },
-        payload: Cow::Borrowed(Into::<&Payload>::into(&payload_slice[0..first_size])).into()
-      };
+    let payload = writer.slice().to_vec();
+    let len = payload.len();
+    let max = self.max_fragment_size;
 
-      // Serialise the first fragment, including an ID update
-      let mut id2 = GrappleMessageId::new(device_id);
-      id2.device_type = mid.device_type;
-      first.update(&mut id2);
-      let mut buf: SmallVec<[u8; 8]> = smallvec::smallvec![0u8; self.max_fragment_size];
-      let mut buf_writer = BufferBitWriter::new(&mut buf);
-      first.write(&mut buf_writer, id2.clone())?;
+    if len <= max {
+      return Ok(FragmentFrames { channel, device_id, mid, payload, transfer: None, index: 0 });
+    }
 
-      consumer(id2.into(), buf_writer.slice());
+    let crc_len = if self.crc { CRC_LEN } else { 0 };
 
-      let mut i = 1;
-      let mut offset = first_size;
-      while offset < payload_slice.len() {
-        let n = self.max_fragment_size.min(payload_slice.len() - offset);
+    // The start fragment needs room for its header, and the standard format's index only has 4 bits
+    let standard = max > STANDARD_START_HEADER_LEN + crc_len
+      && len <= MAX_STANDARD_LEN
+      && (len - (max - STANDARD_START_HEADER_LEN - crc_len)).div_ceil(max) < MAX_STANDARD_FRAGMENTS;
 
-        let mut frag = Fragment {
-          fragment_id: self.fragment_id,
-          index: i,
-          body: FragmentBody::Fragment,
-          payload: Cow::Borrowed(Into::<&Payload>::into(&payload_slice[offset..offset + n])).into()
-        };
+    // With a CRC, the extended start header fills a classic CAN frame, so only then may the start
+    // fragment carry no payload
+    let min_extended_size = match self.crc {
+      true => EXTENDED_START_HEADER_LEN + CRC_LEN,
+      false => EXTENDED_START_HEADER_LEN + 1,
+    };
+
+    if !standard && (max < min_extended_size || len > MAX_EXTENDED_LEN) {
+      return Err(binmarshal::MarshalError::BufferTooSmall.into());
+    }
+
+    let (start_header_len, header_len) = match standard {
+      true => (STANDARD_START_HEADER_LEN + crc_len, 0),
+      false => (EXTENDED_START_HEADER_LEN + crc_len, EXTENDED_HEADER_LEN),
+    };
 
-        // Serialise remaining fragments, including an ID update
-        let mut id3 = GrappleMessageId::new(device_id);
-        id3.device_type = mid.device_type;
-        frag.update(&mut id3);
-        let mut buf = [0u8; 8];
-        let mut buf_writer = BufferBitWriter::new(&mut buf);
-        frag.write(&mut buf_writer, id3.clone())?;
+    // Retained transfers hold their IDs, so if every ID is in use, make room by dropping the oldest.
+    let dest = FragmentDeviceKey { channel, device_type: mid.device_type, device_id };
+    let (fragment_id, generation) = loop {
+      if let Some(id) = self.fragment_ids.entry(dest).or_default().allocate() {
+        break id;
+      }
+      if !self.drop_oldest_retained(Some(dest)) {
+        return Err(FragmentError::IdsExhausted);
+      }
+    };
+    self.stats.fragmented += 1;
 
-        consumer(id3.into(), buf_writer.slice());
+    let frames = FragmentFrames {
+      channel,
+      device_id,
+      mid,
+      transfer: Some(FragmentTransfer {
+        fragment_id,
+        generation,
+        extended: !standard,
+        crc: self.crc.then(|| crc16(&payload)),
+        start_payload_len: max - start_header_len,
+        payload_len: max - header_len,
+      }),
+      payload,
+      index: 0,
+    };
 
-        offset += 8;
-        i += 1;
+    if self.retain > 0 {
+      if self.retained.len() >= self.retain {
+        self.drop_oldest_retained(None);
+      }
+      if let Some(ids) = self.fragment_ids.get_mut(&dest) {
+        ids.retain(fragment_id);
       }
+      self.retained.push_back((dest, frames.clone()));
     }
 
-    Ok(())
+    Ok(frames)
+  }
+}
+
+#[derive(Clone)]
+struct FragmentTransfer {
+  fragment_id: u8,
+  generation: u32,
+  extended: bool,
+  crc: Option<u16>,
+  start_payload_len: usize,
+  payload_len: usize,
+}
+
+// The frames carrying a single message, from FragmentReassemblerTx::fragment
+#[derive(Clone)]
+pub struct FragmentFrames {
+  channel: u8,
+  device_id: u8,
+  mid: MessageId,
+  payload: alloc::vec::Vec<u8>,
+  // None if the message fits in a single frame
+  transfer: Option<FragmentTransfer>,
+  index: u16,
+}
+
+impl FragmentFrames {
+  fn n_frames(&self) -> usize {
+    match &self.transfer {
+      None => 1,
+      Some(transfer) => 1 + self.payload.len().saturating_sub(transfer.start_payload_len).div_ceil(transfer.payload_len),
+    }
   }
-}
\ No newline at end of file
+
+  fn frame(&self, index: u16) -> Option<(MessageId, SmallVec<[u8; 64]>)> {
+    let len = self.payload.len();
+
+    let Some(transfer) = &self.transfer else {
+      return (index == 0).then(|| (self.mid, SmallVec::from_slice(&self.payload)));
+    };
+
+    let (offset, max_n) = match index {
+      0 => (0, transfer.start_payload_len),
+      _ => (transfer.start_payload_len + (index as usize - 1) * transfer.payload_len, transfer.payload_len),
+    };
+    if offset >= len {
+      return None;
+    }
+    let n = max_n.min(len - offset);
+
+    let mut frag = Fragment::new(
+      transfer.fragment_id,
+      transfer.extended,
+      index,
+      match index {
+        0 => FragmentBody::Start { api_class: self.mid.api_class, api_index: self.mid.api_index, total_len: len as u16, crc: transfer.crc },
+        _ => FragmentBody::Fragment,
+      },
+      &self.payload[offset..offset + n],
+    );
+
+    // Serialise the fragment, including an ID update. This can't fail, since the writer grows as
+    // needed and the format was chosen to fit the message.
+    let mut frag_id = GrappleMessageId::new(self.device_id);
+    frag_id.device_type = self.mid.device_type;
+    frag.update(&mut frag_id);
+    let mut writer = VecBitWriter::new();
+    frag.write(&mut writer, frag_id.clone()).ok()?;
+
+    Some((frag_id.into(), SmallVec::from_slice(writer.slice())))
+  }
+}
+
+impl Iterator for FragmentFrames {
+  type Item = (MessageId, SmallVec<[u8; 64]>);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let frame = self.frame(self.index)?;
+    self.index += 1;
+    Some(frame)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = self.n_frames().saturating_sub(self.index as usize);
+    (remaining, Some(remaining))
+  }
+}
+
+impl ExactSizeIterator for FragmentFrames {}
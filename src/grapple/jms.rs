@@ -1,6 +1,8 @@
 use binmarshal::{AsymmetricCow, BitSpecification, Demarshal, LengthTaggedSlice, Marshal, MarshalUpdate, Payload, Proxy};
 use bounded_static::ToStatic;
 
+use crate::ValidateUtf8;
+
 use super::GrappleMessageId;
 
 #[derive(Debug, Clone, PartialEq, Eq, Marshal, Demarshal, ToStatic)]
@@ -131,4 +133,17 @@ pub enum JMSMessage<'a> {
 
   #[marshal(tag = "3")]
   Blink
-}
\ No newline at end of file
+}
+
+impl<'a> ValidateUtf8 for JMSMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      JMSMessage::Update(JMSElectronicsUpdate { update: JMSCardUpdate::Lighting { text_back, text, .. }, .. }) => {
+        text_back.validate_utf8()?;
+        text.validate_utf8()
+      },
+      JMSMessage::Update(JMSElectronicsUpdate { update: JMSCardUpdate::IO(), .. })
+        | JMSMessage::Status(_) | JMSMessage::SetRole(_) | JMSMessage::Blink => Ok(())
+    }
+  }
+}
@@ -1,4 +1,4 @@
-use crate::Validate;
+use crate::{Validate, ValidateUtf8, macros::no_strings};
 use alloc::borrow::Cow;
 use binmarshal::{Proxy, BitSpecification, Marshal, Demarshal, MarshalUpdate};
 use bounded_static::ToStatic;
@@ -15,7 +15,11 @@ pub struct LaserCanRoiU4(pub u8);
 
 impl Marshal<()> for LaserCanRoiU4 {
   fn write<W: binmarshal::BitWriter>(&self, writer: &mut W, _ctx: ()) -> Result<(), binmarshal::MarshalError> {
-    (self.0 - 1).write(writer, BitSpecification::<4>)
+    // Stored as 1-16 on the wire as 0-15. Anything else can't be represented.
+    match self.0 {
+      1..=16 => (self.0 - 1).write(writer, BitSpecification::<4>),
+      _ => Err(binmarshal::MarshalError::CoercionError)
+    }
   }
 }
 
@@ -176,3 +180,17 @@ impl<'a> Validate for LaserCanMessage<'a> {
     }
   }
 }
+
+no_strings!(LaserCanRoi, LaserCanTimingBudget, LaserCanRangingMode);
+
+impl<'a> ValidateUtf8 for LaserCanMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      LaserCanMessage::Measurement(_) => Ok(()),
+      LaserCanMessage::SetRange(req) => req.validate_utf8(),
+      LaserCanMessage::SetRoi(req) => req.validate_utf8(),
+      LaserCanMessage::SetTimingBudget(req) => req.validate_utf8(),
+      LaserCanMessage::SetLedThreshold(req) => req.validate_utf8(),
+    }
+  }
+}
@@ -1,6 +1,8 @@
 use binmarshal::{AsymmetricCow, BitSpecification, Demarshal, Marshal, MarshalUpdate, Payload, Proxy};
 use bounded_static::ToStatic;
 
+use crate::ValidateUtf8;
+
 use super::GrappleMessageId;
 
 #[derive(Clone, Debug, PartialEq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
@@ -22,4 +24,14 @@ pub enum MiscMessage<'a> {
     #[cfg_attr(feature = "serde", serde(borrow))]
     crate::grapple::jms::JMSMessage<'a>
   )
-}
\ No newline at end of file
+}
+
+impl<'a> ValidateUtf8 for MiscMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      MiscMessage::MiscMessage(_) => Ok(()),
+      #[cfg(feature = "grapple_jms")]
+      MiscMessage::JMS(jms) => jms.validate_utf8(),
+    }
+  }
+}
@@ -4,6 +4,8 @@ use bounded_static::ToStatic;
 #[cfg(feature = "pyo3")]
 use pyo3::prelude::*;
 
+use crate::{ValidateUtf8, macros::no_strings};
+
 use super::{errors::GrappleResult, GrappleMessageId, Request};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
@@ -115,4 +117,26 @@ pub enum MitocandriaMessage<'a> {
     #[cfg_attr(feature = "serde", serde(borrow))]
     MitocandriaChannelRequest<'a>
   )
-}
\ No newline at end of file
+}
+
+no_strings!(MitocandriaSwitchableChannelRequest, MitocandriaAdjustableChannelRequest, MitocandriaAdjustableChannelCalibrationRequest);
+
+impl<'a> ValidateUtf8 for MitocandriaChannelRequest<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      MitocandriaChannelRequest::SetSwitchableChannel(req) => req.validate_utf8(),
+      MitocandriaChannelRequest::SetAdjustableChannel(req) => req.validate_utf8(),
+      MitocandriaChannelRequest::CalibrateAdjChannel(req) => req.validate_utf8(),
+      MitocandriaChannelRequest::StartAutoCalibrate(req) => req.validate_utf8(),
+    }
+  }
+}
+
+impl<'a> ValidateUtf8 for MitocandriaMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      MitocandriaMessage::StatusFrame(_) => Ok(()),
+      MitocandriaMessage::ChannelRequest(req) => req.validate_utf8(),
+    }
+  }
+}
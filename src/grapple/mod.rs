@@ -1,7 +1,7 @@
 use binmarshal::{BitWriter, Demarshal, Marshal, MarshalUpdate};
 use bounded_static::ToStatic;
 
-use crate::{DEVICE_TYPE_BROADCAST, DEVICE_TYPE_FIRMWARE_UPGRADE, Validate, MessageId};
+use crate::{DEVICE_TYPE_BROADCAST, DEVICE_TYPE_FIRMWARE_UPGRADE, Validate, ValidateUtf8, MessageId, can::CanFrameFormat};
 use self::{device_info::GrappleDeviceInfo, firmware::GrappleFirmwareMessage, fragments::Fragment, errors::GrappleResult};
 
 pub mod device_info;
@@ -21,6 +21,7 @@ pub mod misc;
 pub mod firmware;
 pub mod fragments;
 pub mod errors;
+pub mod tracker;
 
 pub const MANUFACTURER_GRAPPLE: u8 = 6;
 pub const DEVICE_TYPE_DISTANCE_SENSOR: u8 = 6;
@@ -28,6 +29,8 @@ pub const DEVICE_TYPE_POWER_DISTRIBUTION_MODULE: u8 = 8;
 pub const DEVICE_TYPE_IO_BREAKOUT: u8 = 11;
 pub const DEVICE_TYPE_SPIDERLAN: u8 = 12;
 pub const DEVICE_TYPE_MISC: u8 = 10;
+// Misc messages are tagged with this on the wire, not DEVICE_TYPE_MISC
+pub const DEVICE_TYPE_MISC_WIRE: u8 = 30;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
@@ -107,6 +110,15 @@ impl<'a> Validate for MaybeFragment<'a> {
   }
 }
 
+impl<'a> ValidateUtf8 for MaybeFragment<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      MaybeFragment::Fragment(_) => Ok(()),
+      MaybeFragment::Message(m) => m.validate_utf8(),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "type", content = "data"))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -154,6 +166,15 @@ impl<R: Validate, A> Validate for Request<R, A> {
   }
 }
 
+impl<R: ValidateUtf8, A: ValidateUtf8> ValidateUtf8 for Request<R, A> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      Request::Ack(ack) => ack.validate_utf8(),
+      Request::Request(req) => req.validate_utf8(),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "type", content = "data"))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -197,7 +218,7 @@ pub enum GrappleDeviceMessage<'a> {
     flexican::FlexiCANMessage<'a>
   ),
 
-  #[marshal(tag = "30")]
+  #[marshal(tag = "DEVICE_TYPE_MISC_WIRE")]
   Misc(
     #[marshal(ctx = "forward")]
     #[cfg_attr(feature = "serde", serde(borrow))]
@@ -221,6 +242,22 @@ impl<'a> Validate for GrappleDeviceMessage<'a> {
   }
 }
 
+impl<'a> ValidateUtf8 for GrappleDeviceMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      GrappleDeviceMessage::Broadcast(bc) => bc.validate_utf8(),
+      GrappleDeviceMessage::FirmwareUpdate(fw) => fw.validate_utf8(),
+      #[cfg(feature = "grapple_lasercan")]
+      GrappleDeviceMessage::DistanceSensor(lc) => lc.validate_utf8(),
+      #[cfg(feature = "grapple_mitocandria")]
+      GrappleDeviceMessage::PowerDistributionModule(pdm) => pdm.validate_utf8(),
+      #[cfg(feature = "grapple_flexican")]
+      GrappleDeviceMessage::IOBreakout(io) => io.validate_utf8(),
+      GrappleDeviceMessage::Misc(misc) => misc.validate_utf8(),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Marshal, Demarshal, MarshalUpdate, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(tag = "type", content = "data"))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -242,6 +279,14 @@ impl<'a> Validate for GrappleBroadcastMessage<'a> {
   }
 }
 
+impl<'a> ValidateUtf8 for GrappleBroadcastMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      GrappleBroadcastMessage::DeviceInfo(di) => di.validate_utf8(),
+    }
+  }
+}
+
 #[derive(Debug, Clone, PartialEq, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
@@ -265,3 +310,16 @@ pub fn write_direct<'a, T: BitWriter>(writer: &mut T, mut msg: TaggedGrappleMess
   msg.msg.write(writer, id)?;
   Ok(())
 }
+
+// As with write_direct, but ensures the payload fits in a single frame of the given format, padding it
+// up to the next valid DLC length (12-64 byte steps on CAN FD).
+pub fn write_direct_for_format<'a, T: BitWriter>(writer: &mut T, msg: TaggedGrappleMessage<'a>, format: CanFrameFormat) -> Result<(), binmarshal::MarshalError> {
+  let start = writer.slice().len();
+  write_direct(writer, msg)?;
+  let len = writer.slice().len() - start - 4;
+  let padded = format.padded_len(len).ok_or(binmarshal::MarshalError::BufferTooSmall)?;
+  if padded > len {
+    writer.reserve_and_advance_aligned_slice(padded - len)?.fill(0);
+  }
+  Ok(())
+}
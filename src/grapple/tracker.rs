@@ -0,0 +1,94 @@
+use alloc::borrow::Cow;
+use binmarshal::MarshalUpdate;
+
+use super::{GrappleDeviceMessage, GrappleMessageId, errors::GrappleError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestKey {
+  pub device_type: u8,
+  pub device_id: u8,
+  pub api_class: u8,
+  pub api_index: u8,
+}
+
+impl From<&GrappleMessageId> for RequestKey {
+  fn from(id: &GrappleMessageId) -> Self {
+    Self {
+      device_type: id.device_type,
+      device_id: id.device_id,
+      api_class: id.api_class,
+      api_index: id.api_index,
+    }
+  }
+}
+
+struct OutstandingRequest<T> {
+  key: RequestKey,
+  deadline: i64,
+  tag: T,
+}
+
+// Matches Acks to the Requests that caused them. Requests to the same key are resolved in the order
+// they were sent, since devices process requests sequentially.
+pub struct RequestTracker<T> {
+  outstanding: alloc::vec::Vec<OutstandingRequest<T>>,
+  timeout: i64,
+}
+
+impl<T> RequestTracker<T> {
+  pub fn new(timeout: i64) -> Self {
+    Self { outstanding: alloc::vec![], timeout }
+  }
+
+  pub fn set_timeout(&mut self, timeout: i64) {
+    self.timeout = timeout;
+  }
+
+  pub fn len(&self) -> usize {
+    self.outstanding.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.outstanding.is_empty()
+  }
+
+  pub fn track(&mut self, now: i64, id: &GrappleMessageId, tag: T) {
+    self.outstanding.push(OutstandingRequest { key: id.into(), deadline: now.saturating_add(self.timeout), tag });
+  }
+
+  // Computes the ID of an outgoing message and tracks it, returning the ID so it can be sent.
+  pub fn track_message(&mut self, now: i64, device_id: u8, message: &mut GrappleDeviceMessage, tag: T) -> GrappleMessageId {
+    let mut id = GrappleMessageId::new(device_id);
+    message.update(&mut id);
+    self.track(now, &id, tag);
+    id
+  }
+
+  // Resolve the oldest outstanding request matching an incoming message. Messages without the ack flag
+  // set are never matched.
+  pub fn resolve(&mut self, id: &GrappleMessageId) -> Option<T> {
+    if !id.ack_flag {
+      return None;
+    }
+
+    let key: RequestKey = id.into();
+    let idx = self.outstanding.iter().position(|x| x.key == key)?;
+    Some(self.outstanding.remove(idx).tag)
+  }
+
+  pub fn cancel<F: FnMut(&RequestKey, &T) -> bool>(&mut self, mut predicate: F) {
+    self.outstanding.retain(|x| !predicate(&x.key, &x.tag));
+  }
+
+  pub fn expire<Consumer: FnMut(RequestKey, T, GrappleError<'static>)>(&mut self, now: i64, consumer: &mut Consumer) {
+    let mut i = 0;
+    while i < self.outstanding.len() {
+      if now > self.outstanding[i].deadline {
+        let req = self.outstanding.remove(i);
+        consumer(req.key, req.tag, GrappleError::TimedOut(Cow::Borrowed("No acknowledgement received").into()));
+      } else {
+        i += 1;
+      }
+    }
+  }
+}
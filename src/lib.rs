@@ -7,6 +7,16 @@ pub mod grapple;
 pub mod ni;
 pub mod macros;
 pub mod bridge;
+pub mod can;
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "pyo3")]
+pub mod python;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 pub use binmarshal;
 
@@ -26,6 +36,7 @@ pub const DEVICE_ID_BROADCAST: u8 = 0x3F;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ToStatic)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))] 
 #[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "pyo3", pyo3::pyclass(get_all, set_all, eq))]
 pub struct MessageId {
   pub device_type: u8,
   pub manufacturer: u8,
@@ -34,6 +45,33 @@ pub struct MessageId {
   pub device_id: u8,
 }
 
+#[cfg(feature = "pyo3")]
+#[pyo3::pymethods]
+impl MessageId {
+  #[new]
+  fn py_new(device_type: u8, manufacturer: u8, api_class: u8, api_index: u8, device_id: u8) -> Self {
+    Self { device_type, manufacturer, api_class, api_index, device_id }
+  }
+
+  #[staticmethod]
+  fn from_u32(value: u32) -> Self {
+    value.into()
+  }
+
+  #[pyo3(name = "to_u32")]
+  fn py_to_u32(&self) -> u32 {
+    (*self).into()
+  }
+
+  fn __int__(&self) -> u32 {
+    (*self).into()
+  }
+
+  fn __repr__(&self) -> String {
+    format!("{:?}", self)
+  }
+}
+
 impl From<u32> for MessageId {
   fn from(value: u32) -> Self {
     Self {
@@ -144,3 +182,42 @@ impl<'a> Validate for ManufacturerMessage<'a> {
 pub trait Validate {
   fn validate(&self) -> GrappleResult<()>;
 }
+
+// binmarshal doesn't check that strings are valid UTF-8 when demarshalling, so anything read from the bus
+// has to be checked before its strings are used. MessageDecoder and FragmentReassemblerRx do this already.
+pub trait ValidateUtf8 {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError>;
+}
+
+macros::no_strings!((), bool, u8, u16, i16, u32);
+
+impl<'a> ValidateUtf8 for binmarshal::AsymmetricCow<'a, str> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    core::str::from_utf8(self.as_bytes()).map(|_| ()).map_err(|_| binmarshal::MarshalError::CoercionError)
+  }
+}
+
+impl<T: ValidateUtf8, E: ValidateUtf8> ValidateUtf8 for core::result::Result<T, E> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      Ok(v) => v.validate_utf8(),
+      Err(e) => e.validate_utf8(),
+    }
+  }
+}
+
+impl<'a> ValidateUtf8 for Message<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    self.msg.validate_utf8()
+  }
+}
+
+impl<'a> ValidateUtf8 for ManufacturerMessage<'a> {
+  fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+    match self {
+      #[cfg(feature = "ni")]
+      ManufacturerMessage::Ni(_) => Ok(()),
+      ManufacturerMessage::Grapple(grpl) => grpl.validate_utf8(),
+    }
+  }
+}
@@ -35,3 +35,18 @@ macro_rules! request_factory {
     }
   }
 }
+
+// Implements ValidateUtf8 for types that can't contain a string
+macro_rules! no_strings {
+  ($($ty:ty),*) => {
+    $(
+      impl $crate::ValidateUtf8 for $ty {
+        fn validate_utf8(&self) -> Result<(), binmarshal::MarshalError> {
+          Ok(())
+        }
+      }
+    )*
+  }
+}
+
+pub(crate) use no_strings;
@@ -0,0 +1,259 @@
+use binmarshal::{BitWriter, Marshal, MarshalUpdate, VecBitWriter};
+use bounded_static::IntoBoundedStatic;
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+use crate::{Message, MessageId, ManufacturerMessage, grapple::{self, GrappleMessageId, MaybeFragment, fragments::{DefragmentError, FragmentError, FragmentReassembler, FragmentReassemblerTx}}, transport::{CanFrame, MessageDecoder, encode_message}};
+
+fn marshal_err(e: binmarshal::MarshalError) -> PyErr {
+  PyValueError::new_err(format!("Marshal Error: {:?}", e))
+}
+
+fn fragment_err(e: FragmentError) -> PyErr {
+  PyValueError::new_err(e.to_string())
+}
+
+fn defragment_err(e: DefragmentError) -> PyErr {
+  PyValueError::new_err(e.to_string())
+}
+
+fn json_err(e: serde_json::Error) -> PyErr {
+  PyValueError::new_err(format!("JSON Error: {}", e))
+}
+
+fn json_to_dict(py: Python<'_>, json: String) -> PyResult<PyObject> {
+  Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+fn dict_to_json(py: Python<'_>, dict: &Bound<'_, PyAny>) -> PyResult<String> {
+  py.import("json")?.call_method1("dumps", (dict,))?.extract()
+}
+
+// Wraps an owned message type for Python, converting to and from dicts using the serde representation.
+macro_rules! py_message_wrapper {
+  ($py_name:ident, $name:literal, $ty:ty, $de:ty) => {
+    py_message_wrapper!($py_name, $name, $ty, $de, {});
+  };
+  ($py_name:ident, $name:literal, $ty:ty, $de:ty, { $($methods:tt)* }) => {
+    #[pyclass(name = $name, eq)]
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct $py_name(pub $ty);
+
+    #[pymethods]
+    impl $py_name {
+      #[staticmethod]
+      fn from_json(json: &str) -> PyResult<Self> {
+        Ok(Self(serde_json::from_str::<$de>(json).map_err(json_err)?.into_static()))
+      }
+
+      fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.0).map_err(json_err)
+      }
+
+      #[staticmethod]
+      fn from_dict(py: Python<'_>, dict: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Self::from_json(&dict_to_json(py, dict)?)
+      }
+
+      fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        json_to_dict(py, self.to_json()?)
+      }
+
+      fn __repr__(&self) -> String {
+        format!("{:?}", self.0)
+      }
+
+      $($methods)*
+    }
+  };
+}
+
+py_message_wrapper!(PyGrappleBroadcastMessage, "GrappleBroadcastMessage", grapple::GrappleBroadcastMessage<'static>, grapple::GrappleBroadcastMessage<'_>);
+py_message_wrapper!(PyGrappleFirmwareMessage, "GrappleFirmwareMessage", grapple::firmware::GrappleFirmwareMessage<'static>, grapple::firmware::GrappleFirmwareMessage<'_>);
+#[cfg(feature = "grapple_lasercan")]
+py_message_wrapper!(PyLaserCanMessage, "LaserCanMessage", grapple::lasercan::LaserCanMessage<'static>, grapple::lasercan::LaserCanMessage<'_>);
+#[cfg(feature = "grapple_mitocandria")]
+py_message_wrapper!(PyMitocandriaMessage, "MitocandriaMessage", grapple::mitocandria::MitocandriaMessage<'static>, grapple::mitocandria::MitocandriaMessage<'_>);
+#[cfg(feature = "grapple_flexican")]
+py_message_wrapper!(PyFlexiCANMessage, "FlexiCANMessage", grapple::flexican::FlexiCANMessage<'static>, grapple::flexican::FlexiCANMessage<'_>);
+py_message_wrapper!(PyMiscMessage, "MiscMessage", grapple::misc::MiscMessage<'static>, grapple::misc::MiscMessage<'_>);
+#[cfg(feature = "ni")]
+py_message_wrapper!(PyNiDeviceMessage, "NiDeviceMessage", crate::ni::NiDeviceMessage, crate::ni::NiDeviceMessage);
+py_message_wrapper!(PyGrappleDeviceMessage, "GrappleDeviceMessage", grapple::GrappleDeviceMessage<'static>, grapple::GrappleDeviceMessage<'_>, {
+  // Accepts any of the device family message classes
+  #[new]
+  fn py_new(family: &Bound<'_, PyAny>) -> PyResult<Self> {
+    use grapple::GrappleDeviceMessage as G;
+
+    if let Ok(m) = family.extract::<PyGrappleBroadcastMessage>() { return Ok(Self(G::Broadcast(m.0))) }
+    if let Ok(m) = family.extract::<PyGrappleFirmwareMessage>() { return Ok(Self(G::FirmwareUpdate(m.0))) }
+    #[cfg(feature = "grapple_lasercan")]
+    if let Ok(m) = family.extract::<PyLaserCanMessage>() { return Ok(Self(G::DistanceSensor(m.0))) }
+    #[cfg(feature = "grapple_mitocandria")]
+    if let Ok(m) = family.extract::<PyMitocandriaMessage>() { return Ok(Self(G::PowerDistributionModule(m.0))) }
+    #[cfg(feature = "grapple_flexican")]
+    if let Ok(m) = family.extract::<PyFlexiCANMessage>() { return Ok(Self(G::IOBreakout(m.0))) }
+    if let Ok(m) = family.extract::<PyMiscMessage>() { return Ok(Self(G::Misc(m.0))) }
+
+    Err(PyValueError::new_err("Expected a Grapple device family message"))
+  }
+
+  #[getter]
+  fn family(&self, py: Python<'_>) -> PyResult<PyObject> {
+    use grapple::GrappleDeviceMessage as G;
+
+    Ok(match &self.0 {
+      G::Broadcast(m) => PyGrappleBroadcastMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+      G::FirmwareUpdate(m) => PyGrappleFirmwareMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+      #[cfg(feature = "grapple_lasercan")]
+      G::DistanceSensor(m) => PyLaserCanMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+      #[cfg(feature = "grapple_mitocandria")]
+      G::PowerDistributionModule(m) => PyMitocandriaMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+      #[cfg(feature = "grapple_flexican")]
+      G::IOBreakout(m) => PyFlexiCANMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+      G::Misc(m) => PyMiscMessage(m.clone()).into_pyobject(py)?.into_any().unbind(),
+    })
+  }
+
+  // Encode as a single, unfragmented frame
+  fn encode<'py>(&self, py: Python<'py>, device_id: u8) -> PyResult<(u32, Bound<'py, PyBytes>)> {
+    let mut msg = self.0.clone();
+    let mut id = GrappleMessageId::new(device_id);
+    msg.update(&mut id);
+
+    let mut writer = VecBitWriter::new();
+    msg.write(&mut writer, id.clone()).map_err(marshal_err)?;
+    Ok((MessageId::from(id).into(), PyBytes::new(py, writer.slice())))
+  }
+
+  #[staticmethod]
+  fn decode(id: u32, data: &[u8]) -> PyResult<Self> {
+    match MessageDecoder::decode_raw(id.into(), data).map_err(marshal_err)?.msg {
+      ManufacturerMessage::Grapple(MaybeFragment::Message(m)) => Ok(Self(m.into_static())),
+      _ => Err(PyValueError::new_err("Not an unfragmented Grapple message")),
+    }
+  }
+});
+py_message_wrapper!(PyMessage, "Message", Message<'static>, Message<'_>, {
+  #[new]
+  fn py_new(device_id: u8, msg: PyGrappleDeviceMessage) -> Self {
+    Self(Message::new(device_id, ManufacturerMessage::Grapple(MaybeFragment::Message(msg.0))))
+  }
+
+  #[getter]
+  fn id(&self) -> MessageId {
+    self.0.id
+  }
+
+  // The Grapple device message, or None if this is a fragment or from another manufacturer
+  #[getter]
+  fn grapple(&self) -> Option<PyGrappleDeviceMessage> {
+    match &self.0.msg {
+      ManufacturerMessage::Grapple(MaybeFragment::Message(m)) => Some(PyGrappleDeviceMessage(m.clone())),
+      _ => None,
+    }
+  }
+
+  #[cfg(feature = "ni")]
+  #[getter]
+  fn ni(&self) -> Option<PyNiDeviceMessage> {
+    match &self.0.msg {
+      ManufacturerMessage::Ni(m) => Some(PyNiDeviceMessage(m.clone())),
+      _ => None,
+    }
+  }
+
+  #[getter]
+  fn is_fragment(&self) -> bool {
+    matches!(self.0.msg, ManufacturerMessage::Grapple(MaybeFragment::Fragment(_)))
+  }
+
+  // Encode as a single, unfragmented frame
+  fn encode<'py>(&self, py: Python<'py>) -> PyResult<(u32, Bound<'py, PyBytes>)> {
+    let mut msg = self.0.clone();
+    msg.update(&mut ());
+
+    let mut writer = VecBitWriter::new();
+    msg.msg.write(&mut writer, msg.id).map_err(marshal_err)?;
+    Ok((msg.id.into(), PyBytes::new(py, writer.slice())))
+  }
+
+  // Decode a single frame. Fragments are returned as-is, use FragmentReassembler to reassemble them.
+  #[staticmethod]
+  fn decode(id: u32, data: &[u8]) -> PyResult<Self> {
+    Ok(Self(MessageDecoder::decode_raw(id.into(), data).map_err(marshal_err)?.into_static()))
+  }
+});
+
+#[pyclass(name = "FragmentReassembler")]
+pub struct PyFragmentReassembler {
+  decoder: MessageDecoder,
+  tx: FragmentReassemblerTx,
+}
+
+#[pymethods]
+impl PyFragmentReassembler {
+  #[new]
+  #[pyo3(signature = (age_off, max_fragment_size = 8))]
+  fn py_new(age_off: i64, max_fragment_size: usize) -> Self {
+    let (_, tx) = FragmentReassembler::new(age_off, max_fragment_size).split();
+    Self { decoder: MessageDecoder::new(age_off), tx }
+  }
+
+  // Feed a received frame, returning the decoded message once it is complete
+  fn defragment(&mut self, now: i64, id: u32, data: &[u8]) -> PyResult<Option<PyMessage>> {
+    let frame = CanFrame::new(id.into(), now, data);
+    Ok(self.decoder.decode(&frame).map_err(defragment_err)?.map(PyMessage))
+  }
+
+  // Encode a message into the frames required to send it, fragmenting if necessary
+  fn fragment<'py>(&mut self, py: Python<'py>, message: &PyMessage) -> PyResult<Vec<(u32, Bound<'py, PyBytes>)>> {
+    let mut msg = message.0.clone();
+    msg.update(&mut ());
+
+    let mut frames = vec![];
+    encode_message(&mut self.tx, msg, &mut |id, data| frames.push((Into::<u32>::into(id), PyBytes::new(py, data))))
+      .map_err(fragment_err)?;
+    Ok(frames)
+  }
+}
+
+// Add all protocol classes to a Python module
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add_class::<MessageId>()?;
+  m.add_class::<PyMessage>()?;
+  m.add_class::<PyGrappleDeviceMessage>()?;
+  m.add_class::<PyGrappleBroadcastMessage>()?;
+  m.add_class::<PyGrappleFirmwareMessage>()?;
+  m.add_class::<PyMiscMessage>()?;
+  m.add_class::<PyFragmentReassembler>()?;
+  m.add_class::<grapple::errors::GrappleResultPy>()?;
+
+  #[cfg(feature = "ni")]
+  m.add_class::<PyNiDeviceMessage>()?;
+
+  #[cfg(feature = "grapple_lasercan")]
+  {
+    use grapple::lasercan::*;
+    m.add_class::<PyLaserCanMessage>()?;
+    m.add_class::<LaserCanRoi>()?;
+    m.add_class::<LaserCanTimingBudget>()?;
+    m.add_class::<LaserCanRangingMode>()?;
+    m.add_class::<LaserCanMeasurement>()?;
+  }
+
+  #[cfg(feature = "grapple_mitocandria")]
+  {
+    use grapple::mitocandria::*;
+    m.add_class::<PyMitocandriaMessage>()?;
+    m.add_class::<MitocandriaChannelStatus>()?;
+    m.add_class::<MitocandriaStatusFrame>()?;
+    m.add_class::<MitocandriaSwitchableChannelRequest>()?;
+    m.add_class::<MitocandriaAdjustableChannelRequest>()?;
+    m.add_class::<MitocandriaAdjustableChannelCalibrationRequest>()?;
+  }
+
+  #[cfg(feature = "grapple_flexican")]
+  m.add_class::<PyFlexiCANMessage>()?;
+
+  Ok(())
+}
@@ -0,0 +1,97 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::Instant};
+
+use crate::{MessageId, can::CanFrameFormat};
+
+use super::{CanFrame, CanTransport, TransportError};
+
+struct LoopbackNodeState {
+  id: usize,
+  queue: VecDeque<CanFrame>,
+}
+
+struct LoopbackBusState {
+  nodes: Vec<LoopbackNodeState>,
+  next_id: usize,
+}
+
+// An in-memory CAN bus. Every frame sent by a node is delivered to every other node on the bus, in order.
+#[derive(Clone)]
+pub struct LoopbackBus {
+  state: Arc<Mutex<LoopbackBusState>>,
+  format: CanFrameFormat,
+  epoch: Instant,
+}
+
+impl LoopbackBus {
+  pub fn new(format: CanFrameFormat) -> Self {
+    Self {
+      state: Arc::new(Mutex::new(LoopbackBusState { nodes: vec![], next_id: 0 })),
+      format,
+      epoch: Instant::now(),
+    }
+  }
+
+  pub fn node(&self) -> LoopbackNode {
+    let mut state = self.state.lock().unwrap();
+    let id = state.next_id;
+    state.next_id += 1;
+    state.nodes.push(LoopbackNodeState { id, queue: VecDeque::new() });
+    LoopbackNode { bus: self.clone(), id, echo: false }
+  }
+
+  fn now(&self) -> i64 {
+    self.epoch.elapsed().as_micros() as i64
+  }
+}
+
+pub struct LoopbackNode {
+  bus: LoopbackBus,
+  id: usize,
+  echo: bool,
+}
+
+impl LoopbackNode {
+  // Also deliver frames sent by this node back to itself, like SocketCAN's CAN_RAW_RECV_OWN_MSGS
+  pub fn set_echo(&mut self, echo: bool) {
+    self.echo = echo;
+  }
+}
+
+impl Drop for LoopbackNode {
+  fn drop(&mut self) {
+    if let Ok(mut state) = self.bus.state.lock() {
+      state.nodes.retain(|n| n.id != self.id);
+    }
+  }
+}
+
+impl CanTransport for LoopbackNode {
+  type Error = TransportError;
+
+  fn format(&self) -> CanFrameFormat {
+    self.bus.format
+  }
+
+  fn send(&mut self, id: MessageId, data: &[u8]) -> Result<(), Self::Error> {
+    if data.len() > self.bus.format.max_data_len() {
+      return Err(TransportError::FrameTooLong);
+    }
+
+    let frame = CanFrame::new(id, self.bus.now(), data);
+    let mut state = self.bus.state.lock().map_err(|_| TransportError::Disconnected)?;
+    for node in state.nodes.iter_mut().filter(|n| self.echo || n.id != self.id) {
+      node.queue.push_back(frame.clone());
+    }
+    Ok(())
+  }
+
+  fn recv(&mut self) -> Result<Option<CanFrame>, Self::Error> {
+    let mut state = self.bus.state.lock().map_err(|_| TransportError::Disconnected)?;
+    let node = state.nodes.iter_mut().find(|n| n.id == self.id).ok_or(TransportError::Disconnected)?;
+    Ok(node.queue.pop_front())
+  }
+
+  fn now(&self) -> i64 {
+    self.bus.now()
+  }
+}
@@ -0,0 +1,233 @@
+use binmarshal::{BitView, BitWriter, BufferBitWriter, Demarshal, Marshal};
+use bounded_static::ToBoundedStatic;
+use smallvec::SmallVec;
+
+use crate::{ManufacturerMessage, Message, MessageId, ValidateUtf8, can::CanFrameFormat, grapple::{GrappleDeviceMessage, GrappleMessageId, MaybeFragment, MANUFACTURER_GRAPPLE, fragments::{DefragmentError, FragmentBody, FragmentError, FragmentReassembler, FragmentReassemblerRx, FragmentReassemblerTx}}};
+
+#[cfg(feature = "std")]
+pub mod loopback;
+#[cfg(all(feature = "socketcan", target_os = "linux"))]
+pub mod socketcan;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CanFrame {
+  pub id: MessageId,
+  // Microseconds, relative to an epoch chosen by the transport
+  pub timestamp: i64,
+  pub data: SmallVec<[u8; 64]>,
+}
+
+impl CanFrame {
+  pub fn new(id: MessageId, timestamp: i64, data: &[u8]) -> Self {
+    Self { id, timestamp, data: SmallVec::from_slice(data) }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+  FrameTooLong,
+  Disconnected,
+}
+
+pub trait CanTransport {
+  type Error;
+
+  fn format(&self) -> CanFrameFormat {
+    CanFrameFormat::Classic
+  }
+
+  fn send(&mut self, id: MessageId, data: &[u8]) -> Result<(), Self::Error>;
+
+  // Non-blocking, returns None if no frame is waiting.
+  fn recv(&mut self) -> Result<Option<CanFrame>, Self::Error>;
+
+  // Current time in the same units and epoch as received frame timestamps.
+  fn now(&self) -> i64;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrappleTransportError<E> {
+  Transport(E),
+  Marshal(binmarshal::MarshalError),
+  Fragment(FragmentError),
+  Defragment(DefragmentError),
+}
+
+// Sends and receives Grapple messages over any CanTransport, fragmenting and reassembling as required.
+pub struct GrappleTransport<T: CanTransport> {
+  transport: T,
+  rx: FragmentReassemblerRx,
+  tx: FragmentReassemblerTx,
+}
+
+impl<T: CanTransport> GrappleTransport<T> {
+  pub fn new(transport: T, age_off: i64) -> Self {
+    let (rx, tx) = FragmentReassembler::new_for_format(age_off, transport.format()).split();
+    Self { transport, rx, tx }
+  }
+
+  pub fn transport(&self) -> &T {
+    &self.transport
+  }
+
+  pub fn transport_mut(&mut self) -> &mut T {
+    &mut self.transport
+  }
+
+  pub fn into_inner(self) -> T {
+    self.transport
+  }
+
+  pub fn rx(&self) -> &FragmentReassemblerRx {
+    &self.rx
+  }
+
+  pub fn tx(&self) -> &FragmentReassemblerTx {
+    &self.tx
+  }
+
+  // For configuring the reassembler, e.g. to enable NACKs or CRCs
+  pub fn rx_mut(&mut self) -> &mut FragmentReassemblerRx {
+    &mut self.rx
+  }
+
+  pub fn tx_mut(&mut self) -> &mut FragmentReassemblerTx {
+    &mut self.tx
+  }
+
+  pub fn send(&mut self, device_id: u8, message: GrappleDeviceMessage) -> Result<(), GrappleTransportError<T::Error>> {
+    let transport = &mut self.transport;
+    let mut result = Ok(());
+    self.tx.maybe_fragment(device_id, message, &mut |id, data| {
+      if result.is_ok() {
+        result = transport.send(id, data);
+      }
+    }).map_err(GrappleTransportError::Fragment)?;
+    result.map_err(GrappleTransportError::Transport)
+  }
+
+  // Receive the next complete Grapple message, if any. Frames from other manufacturers are skipped.
+  // NACKs are answered as they arrive, and once there are no frames waiting, any NACKs that are due
+  // are sent.
+  pub fn recv(&mut self) -> Result<Option<(GrappleMessageId, GrappleDeviceMessage<'static>)>, GrappleTransportError<T::Error>> {
+    while let Some(frame) = self.transport.recv().map_err(GrappleTransportError::Transport)? {
+      if frame.id.manufacturer != MANUFACTURER_GRAPPLE {
+        continue;
+      }
+
+      let mut view = BitView::new(&frame.data[..]);
+      let msg = MaybeFragment::read(&mut view, frame.id.into()).map_err(GrappleTransportError::Marshal)?;
+
+      if let MaybeFragment::Fragment(nack) = &msg {
+        if matches!(nack.body(), FragmentBody::Nack { .. }) {
+          let transport = &mut self.transport;
+          let mut result = Ok(());
+          self.tx.resend(&frame.id, nack, &mut |id, data| {
+            if result.is_ok() {
+              result = transport.send(id, data);
+            }
+          });
+          result.map_err(GrappleTransportError::Transport)?;
+          continue;
+        }
+      }
+
+      if let Some(msg) = self.rx.defragment_owned(frame.timestamp, &frame.id, msg).map_err(GrappleTransportError::Defragment)? {
+        return Ok(Some((msg.id, msg.message)));
+      }
+    }
+
+    let now = self.transport.now();
+    let transport = &mut self.transport;
+    let mut result = Ok(());
+    self.rx.poll_nacks(now, &mut |id, data| {
+      if result.is_ok() {
+        result = transport.send(id, data);
+      }
+    });
+    result.map_err(GrappleTransportError::Transport)?;
+    Ok(None)
+  }
+}
+
+// Decodes raw frames into Messages, reassembling Grapple fragments along the way.
+pub struct MessageDecoder {
+  rx: FragmentReassemblerRx,
+}
+
+impl MessageDecoder {
+  pub fn new(age_off: i64) -> Self {
+    let (rx, _) = FragmentReassembler::new(age_off, 8).split();
+    Self { rx }
+  }
+
+  // The reassembler, for its fragment stats
+  pub fn reassembler(&self) -> &FragmentReassemblerRx {
+    &self.rx
+  }
+
+  pub fn reassembler_mut(&mut self) -> &mut FragmentReassemblerRx {
+    &mut self.rx
+  }
+
+  // Decode a single frame without reassembly. Fragments are returned as-is.
+  pub fn decode_raw<'a>(id: MessageId, data: &'a [u8]) -> Result<Message<'a>, binmarshal::MarshalError> {
+    let mut view = BitView::new(data);
+    let msg = Message { id, msg: ManufacturerMessage::read(&mut view, id)? };
+    msg.validate_utf8()?;
+    Ok(msg)
+  }
+
+  // Returns None if the frame is part of a fragmented message that isn't yet complete.
+  pub fn decode(&mut self, frame: &CanFrame) -> Result<Option<Message<'static>>, DefragmentError> {
+    self.decode_on_channel(0, frame)
+  }
+
+  // For decoding frames from several buses. Fragments are only reassembled with others from the same
+  // channel, see FragmentReassemblerRx::defragment_on_channel.
+  pub fn decode_on_channel(&mut self, channel: u8, frame: &CanFrame) -> Result<Option<Message<'static>>, DefragmentError> {
+    let msg = Self::decode_raw(frame.id, &frame.data[..])?;
+    self.reassemble_on_channel(channel, frame.timestamp, msg)
+  }
+
+  // As `decode`, for a frame already decoded with `decode_raw`
+  pub fn reassemble(&mut self, timestamp: i64, msg: Message) -> Result<Option<Message<'static>>, DefragmentError> {
+    self.reassemble_on_channel(0, timestamp, msg)
+  }
+
+  pub fn reassemble_on_channel(&mut self, channel: u8, timestamp: i64, msg: Message) -> Result<Option<Message<'static>>, DefragmentError> {
+    match msg {
+      Message { id, msg: ManufacturerMessage::Grapple(grpl) } => {
+        match self.rx.defragment_owned_on_channel(channel, timestamp, &id, grpl)? {
+          Some(msg) => Ok(Some(Message {
+            id: msg.id.into(),
+            msg: ManufacturerMessage::Grapple(MaybeFragment::Message(msg.message))
+          })),
+          None => Ok(None),
+        }
+      },
+      other => Ok(Some(other.to_static())),
+    }
+  }
+}
+
+// Encode a Message into one or more frames, fragmenting Grapple messages that don't fit in a single frame.
+pub fn encode_message<Consumer: FnMut(MessageId, &[u8])>(tx: &mut FragmentReassemblerTx, message: Message, consumer: &mut Consumer) -> Result<(), FragmentError> {
+  encode_message_on_channel(tx, 0, message, consumer)
+}
+
+// As `encode_message`, for a message sent on one channel's bus
+pub fn encode_message_on_channel<Consumer: FnMut(MessageId, &[u8])>(tx: &mut FragmentReassemblerTx, channel: u8, message: Message, consumer: &mut Consumer) -> Result<(), FragmentError> {
+  match message.msg {
+    ManufacturerMessage::Grapple(MaybeFragment::Message(msg)) => tx.maybe_fragment_on_channel(channel, message.id.device_id, msg, consumer),
+    msg => {
+      let mut buf = [0u8; 64];
+      let mut writer = BufferBitWriter::new(&mut buf);
+      msg.write(&mut writer, message.id)?;
+      consumer(message.id, writer.slice());
+      Ok(())
+    }
+  }
+}
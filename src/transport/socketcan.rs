@@ -0,0 +1,148 @@
+use std::{ffi::CString, io, mem, os::fd::{AsRawFd, FromRawFd, OwnedFd}, time::Instant};
+
+use crate::{MessageId, can::CanFrameFormat};
+
+use super::{CanFrame, CanTransport};
+
+// Raw SocketCAN socket bound to a single interface (e.g. can0, vcan0). Only 29-bit extended frames are
+// passed through, since that's all FRC devices use.
+pub struct SocketCanTransport {
+  fd: OwnedFd,
+  format: CanFrameFormat,
+  epoch: Instant,
+}
+
+impl SocketCanTransport {
+  pub fn open(ifname: &str) -> io::Result<Self> {
+    Self::open_with_format(ifname, CanFrameFormat::Classic)
+  }
+
+  pub fn open_fd(ifname: &str) -> io::Result<Self> {
+    Self::open_with_format(ifname, CanFrameFormat::Fd)
+  }
+
+  pub fn open_with_format(ifname: &str, format: CanFrameFormat) -> io::Result<Self> {
+    let name = CString::new(ifname).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if ifindex == 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    let raw = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, libc::CAN_RAW) };
+    if raw < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+    let transport = Self { fd, format, epoch: Instant::now() };
+
+    if format == CanFrameFormat::Fd {
+      transport.set_option(libc::CAN_RAW_FD_FRAMES, 1)?;
+    }
+
+    let mut addr: libc::sockaddr_can = unsafe { mem::zeroed() };
+    addr.can_family = libc::AF_CAN as libc::sa_family_t;
+    addr.can_ifindex = ifindex as libc::c_int;
+
+    let r = unsafe {
+      libc::bind(
+        transport.fd.as_raw_fd(),
+        &addr as *const libc::sockaddr_can as *const libc::sockaddr,
+        mem::size_of::<libc::sockaddr_can>() as libc::socklen_t
+      )
+    };
+    if r < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(transport)
+  }
+
+  pub fn set_recv_own_msgs(&self, enabled: bool) -> io::Result<()> {
+    self.set_option(libc::CAN_RAW_RECV_OWN_MSGS, enabled as libc::c_int)
+  }
+
+  fn set_option(&self, option: libc::c_int, value: libc::c_int) -> io::Result<()> {
+    let r = unsafe {
+      libc::setsockopt(
+        self.fd.as_raw_fd(),
+        libc::SOL_CAN_RAW,
+        option,
+        &value as *const libc::c_int as *const libc::c_void,
+        mem::size_of::<libc::c_int>() as libc::socklen_t
+      )
+    };
+    if r < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+impl AsRawFd for SocketCanTransport {
+  fn as_raw_fd(&self) -> std::os::fd::RawFd {
+    self.fd.as_raw_fd()
+  }
+}
+
+impl CanTransport for SocketCanTransport {
+  type Error = io::Error;
+
+  fn format(&self) -> CanFrameFormat {
+    self.format
+  }
+
+  fn send(&mut self, id: MessageId, data: &[u8]) -> Result<(), Self::Error> {
+    let len = self.format.padded_len(data.len())
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Frame too long for bus"))?;
+
+    let mut frame: libc::canfd_frame = unsafe { mem::zeroed() };
+    frame.can_id = Into::<u32>::into(id) | libc::CAN_EFF_FLAG;
+    frame.len = len as u8;
+    frame.data[..data.len()].copy_from_slice(data);
+
+    // Classic frames share a layout with the head of canfd_frame, so only the size differs.
+    let size = match self.format {
+      CanFrameFormat::Classic => libc::CAN_MTU,
+      CanFrameFormat::Fd => libc::CANFD_MTU,
+    };
+
+    let r = unsafe { libc::write(self.fd.as_raw_fd(), &frame as *const libc::canfd_frame as *const libc::c_void, size) };
+    if r < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  fn recv(&mut self) -> Result<Option<CanFrame>, Self::Error> {
+    loop {
+      let mut frame: libc::canfd_frame = unsafe { mem::zeroed() };
+      let r = unsafe { libc::read(self.fd.as_raw_fd(), &mut frame as *mut libc::canfd_frame as *mut libc::c_void, libc::CANFD_MTU) };
+      if r < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+          io::ErrorKind::WouldBlock => Ok(None),
+          _ => Err(err)
+        };
+      }
+
+      let max_len = match r as usize {
+        libc::CAN_MTU => libc::CAN_MAX_DLEN,
+        libc::CANFD_MTU => libc::CANFD_MAX_DLEN,
+        _ => continue,
+      };
+
+      if frame.can_id & libc::CAN_EFF_FLAG == 0 || frame.can_id & (libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG) != 0 {
+        continue;
+      }
+
+      let id = MessageId::from(frame.can_id & libc::CAN_EFF_MASK);
+      let len = (frame.len as usize).min(max_len);
+      return Ok(Some(CanFrame::new(id, self.now(), &frame.data[..len])));
+    }
+  }
+
+  fn now(&self) -> i64 {
+    self.epoch.elapsed().as_micros() as i64
+  }
+}
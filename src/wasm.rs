@@ -0,0 +1,189 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+
+use binmarshal::{BitWriter, Marshal, MarshalUpdate, VecBitWriter};
+use bounded_static::IntoBoundedStatic;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::{DEVICE_ID_BROADCAST, ManufacturerMessage, Message, grapple::{GrappleBroadcastMessage, GrappleDeviceMessage, MaybeFragment, device_info::{GrappleDeviceInfo, GrappleModelId}, fragments::{DefragmentError, FragmentError, FragmentReassembler, FragmentReassemblerTx}}, transport::{CanFrame, MessageDecoder, encode_message}};
+
+fn marshal_err(e: binmarshal::MarshalError) -> JsError {
+  JsError::new(&format!("Marshal Error: {:?}", e))
+}
+
+fn fragment_err(e: FragmentError) -> JsError {
+  JsError::new(&e.to_string())
+}
+
+fn defragment_err(e: DefragmentError) -> JsError {
+  JsError::new(&e.to_string())
+}
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsError> {
+  // Maps become plain objects and 64-bit integers become numbers, so the output matches the JSON representation
+  value.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn message_from_js(message: JsValue) -> Result<Message<'static>, JsError> {
+  Ok(Message::deserialize(serde_wasm_bindgen::Deserializer::from(message)).map_err(|e| JsError::new(&e.to_string()))?.into_static())
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+  pub id: u32,
+  data: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Frame {
+  #[wasm_bindgen(constructor)]
+  pub fn new(id: u32, data: Vec<u8>) -> Self {
+    Self { id, data }
+  }
+
+  #[wasm_bindgen(getter)]
+  pub fn data(&self) -> Vec<u8> {
+    self.data.clone()
+  }
+}
+
+// Decode a single frame. Fragments are returned as-is, use FragmentReassembler to reassemble them.
+#[wasm_bindgen]
+pub fn decode(id: u32, data: &[u8]) -> Result<JsValue, JsError> {
+  to_js(&MessageDecoder::decode_raw(id.into(), data).map_err(marshal_err)?)
+}
+
+// Encode a message as a single, unfragmented frame
+#[wasm_bindgen]
+pub fn encode(message: JsValue) -> Result<Frame, JsError> {
+  let mut msg = message_from_js(message)?;
+  msg.update(&mut ());
+
+  let mut writer = VecBitWriter::new();
+  msg.msg.write(&mut writer, msg.id).map_err(marshal_err)?;
+  Ok(Frame { id: msg.id.into(), data: writer.slice().to_vec() })
+}
+
+// JSON schema for Message, as accepted by encode and produced by decode
+#[wasm_bindgen]
+pub fn schema() -> Result<JsValue, JsError> {
+  to_js(&schemars::schema_for!(Message))
+}
+
+#[wasm_bindgen(js_name = "FragmentReassembler")]
+pub struct WasmFragmentReassembler {
+  decoder: MessageDecoder,
+  tx: FragmentReassemblerTx,
+}
+
+#[wasm_bindgen(js_class = "FragmentReassembler")]
+impl WasmFragmentReassembler {
+  #[wasm_bindgen(constructor)]
+  pub fn new(age_off: i64, max_fragment_size: usize) -> Self {
+    let (_, tx) = FragmentReassembler::new(age_off, max_fragment_size).split();
+    Self { decoder: MessageDecoder::new(age_off), tx }
+  }
+
+  // Feed a received frame, returning the decoded message once it is complete
+  pub fn defragment(&mut self, now: i64, id: u32, data: &[u8]) -> Result<JsValue, JsError> {
+    match self.decoder.decode(&CanFrame::new(id.into(), now, data)).map_err(defragment_err)? {
+      Some(msg) => to_js(&msg),
+      None => Ok(JsValue::UNDEFINED),
+    }
+  }
+
+  // Encode a message into the frames required to send it, fragmenting if necessary
+  pub fn fragment(&mut self, message: JsValue) -> Result<Vec<Frame>, JsError> {
+    let mut msg = message_from_js(message)?;
+    msg.update(&mut ());
+
+    let mut frames = Vec::new();
+    encode_message(&mut self.tx, msg, &mut |id, data| frames.push(Frame { id: id.into(), data: data.to_vec() }))
+      .map_err(fragment_err)?;
+    Ok(frames)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EnumeratedDevice {
+  pub device_id: u8,
+  pub model_id: GrappleModelId,
+  pub serial: u32,
+  pub is_dfu: bool,
+  pub is_dfu_in_progress: bool,
+  pub version: String,
+  pub name: String,
+  pub last_seen: i64,
+}
+
+// Tracks the Grapple devices on the bus from their responses to an enumeration request. Devices are
+// keyed by serial, since device IDs may clash until they're reassigned.
+#[wasm_bindgen]
+pub struct DeviceEnumerator {
+  reassembler: WasmFragmentReassembler,
+  devices: Vec<EnumeratedDevice>,
+}
+
+#[wasm_bindgen]
+impl DeviceEnumerator {
+  #[wasm_bindgen(constructor)]
+  pub fn new(age_off: i64) -> Self {
+    Self { reassembler: WasmFragmentReassembler::new(age_off, 8), devices: Vec::new() }
+  }
+
+  // The frame(s) to broadcast to ask all Grapple devices to identify themselves
+  pub fn request(&mut self) -> Result<Vec<Frame>, JsError> {
+    let msg = Message::new(DEVICE_ID_BROADCAST, ManufacturerMessage::Grapple(MaybeFragment::Message(
+      GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::EnumerateRequest))
+    )));
+
+    let mut frames = Vec::new();
+    encode_message(&mut self.reassembler.tx, msg, &mut |id, data| frames.push(Frame { id: id.into(), data: data.to_vec() }))
+      .map_err(fragment_err)?;
+    Ok(frames)
+  }
+
+  // Feed a received frame. Returns the decoded message once complete, recording it if it's an enumerate response.
+  pub fn handle(&mut self, now: i64, id: u32, data: &[u8]) -> Result<JsValue, JsError> {
+    let msg = match self.reassembler.decoder.decode(&CanFrame::new(id.into(), now, data)).map_err(defragment_err)? {
+      Some(msg) => msg,
+      None => return Ok(JsValue::UNDEFINED),
+    };
+
+    if let ManufacturerMessage::Grapple(MaybeFragment::Message(GrappleDeviceMessage::Broadcast(
+      GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::EnumerateResponse { model_id, serial, is_dfu, is_dfu_in_progress, version, name })
+    ))) = &msg.msg {
+      let device = EnumeratedDevice {
+        device_id: msg.id.device_id,
+        model_id: model_id.clone(),
+        serial: *serial,
+        is_dfu: *is_dfu,
+        is_dfu_in_progress: *is_dfu_in_progress,
+        version: version.0.to_string(),
+        name: name.0.to_string(),
+        last_seen: now,
+      };
+
+      match self.devices.iter_mut().find(|d| d.serial == *serial) {
+        Some(existing) => *existing = device,
+        None => self.devices.push(device),
+      }
+    }
+
+    to_js(&msg)
+  }
+
+  // Forget devices that haven't responded since `now - timeout`
+  pub fn prune(&mut self, now: i64, timeout: i64) {
+    self.devices.retain(|d| now - d.last_seen <= timeout);
+  }
+
+  pub fn clear(&mut self) {
+    self.devices.clear();
+  }
+
+  pub fn devices(&self) -> Result<JsValue, JsError> {
+    to_js(&self.devices)
+  }
+}
@@ -0,0 +1,88 @@
+use std::borrow::Cow;
+
+use binmarshal::{AsymmetricCow, BitWriter, VecBitWriter};
+use grapple_frc_msgs::{
+  can::{CanFrameFormat, CANFD_DLC_LENGTHS},
+  grapple::{misc::MiscMessage, write_direct, write_direct_for_format, GrappleDeviceMessage, TaggedGrappleMessage},
+};
+
+// (payload length, padded length, DLC) either side of each CAN FD step
+const FD_BOUNDARIES: &[(usize, usize, u8)] = &[
+  (0, 0, 0), (7, 7, 7), (8, 8, 8),
+  (9, 12, 9), (12, 12, 9),
+  (13, 16, 10), (16, 16, 10),
+  (17, 20, 11), (20, 20, 11),
+  (21, 24, 12), (24, 24, 12),
+  (25, 32, 13), (32, 32, 13),
+  (33, 48, 14), (48, 48, 14),
+  (49, 64, 15), (64, 64, 15),
+];
+
+fn misc(payload: &[u8]) -> TaggedGrappleMessage<'_> {
+  TaggedGrappleMessage::new(1, GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(AsymmetricCow(Cow::Borrowed(payload.into())))))
+}
+
+#[test]
+fn fd_dlc_boundaries() {
+  let fd = CanFrameFormat::Fd;
+  for &(len, padded, dlc) in FD_BOUNDARIES {
+    assert_eq!(fd.padded_len(len), Some(padded), "len {}", len);
+    assert_eq!(fd.dlc_for_len(len), Some(dlc), "len {}", len);
+    assert_eq!(fd.len_for_dlc(dlc), padded, "dlc {}", dlc);
+  }
+
+  assert_eq!(fd.padded_len(65), None);
+  assert_eq!(fd.dlc_for_len(65), None);
+
+  // Every length pads to the smallest DLC length that holds it
+  for len in 0..=64 {
+    let padded = fd.padded_len(len).unwrap();
+    assert!(padded >= len && CANFD_DLC_LENGTHS.contains(&padded), "len {}", len);
+    assert!(CANFD_DLC_LENGTHS.iter().all(|&l| l < len || l >= padded), "len {}", len);
+  }
+}
+
+#[test]
+fn classic_dlc() {
+  let classic = CanFrameFormat::Classic;
+  for len in 0..=8 {
+    assert_eq!(classic.padded_len(len), Some(len));
+    assert_eq!(classic.dlc_for_len(len), Some(len as u8));
+    assert_eq!(classic.len_for_dlc(len as u8), len);
+  }
+
+  assert_eq!(classic.padded_len(9), None);
+  assert_eq!(classic.dlc_for_len(9), None);
+  // DLCs above 8 still mean 8 bytes on a classic bus
+  for dlc in 9..=15 {
+    assert_eq!(classic.len_for_dlc(dlc), 8);
+  }
+}
+
+#[test]
+fn write_direct_padding() {
+  for &(len, padded, _) in FD_BOUNDARIES {
+    let payload = vec![0xA5; len];
+
+    let mut unpadded = VecBitWriter::new();
+    write_direct(&mut unpadded, misc(&payload)).unwrap();
+
+    let mut writer = VecBitWriter::new();
+    write_direct_for_format(&mut writer, misc(&payload), CanFrameFormat::Fd).unwrap();
+
+    // The 4 byte ID, the message, then zeros up to the DLC length
+    let out = writer.slice();
+    assert_eq!(out.len(), 4 + padded, "len {}", len);
+    assert_eq!(&out[..4 + len], unpadded.slice(), "len {}", len);
+    assert!(out[4 + len..].iter().all(|&b| b == 0), "len {}", len);
+
+    let mut writer = VecBitWriter::new();
+    let classic = write_direct_for_format(&mut writer, misc(&payload), CanFrameFormat::Classic);
+    match len <= 8 {
+      true => assert_eq!(writer.slice(), unpadded.slice(), "len {}", len),
+      false => assert!(classic.is_err(), "len {}", len),
+    }
+  }
+
+  assert!(write_direct_for_format(&mut VecBitWriter::new(), misc(&[0; 65]), CanFrameFormat::Fd).is_err());
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "capi")]
+
+// The build script generates the C header into OUT_DIR, since build scripts mustn't touch the source
+// tree. This keeps the checked-in copy in step. Run with GRPL_UPDATE_HEADER=1 to update it.
+
+const GENERATED: &str = include_str!(concat!(env!("OUT_DIR"), "/grapple_frc_msgs.h"));
+const HEADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/include/grapple_frc_msgs.h");
+
+#[test]
+fn header_is_up_to_date() {
+  if std::env::var_os("GRPL_UPDATE_HEADER").is_some() {
+    std::fs::write(HEADER_PATH, GENERATED).unwrap();
+  }
+
+  let checked_in = std::fs::read_to_string(HEADER_PATH).unwrap();
+  assert!(checked_in == GENERATED, "include/grapple_frc_msgs.h is out of date, rerun with GRPL_UPDATE_HEADER=1");
+}
+
+#[test]
+fn null_handles() {
+  use grapple_frc_msgs::capi::*;
+
+  unsafe {
+    assert_eq!(grpl_message_id(core::ptr::null()), 0);
+    assert_eq!(grpl_message_decode(0, core::ptr::null(), 0, core::ptr::null_mut()), GrplStatus::NullPointer);
+    grpl_message_free(core::ptr::null_mut());
+  }
+}
+
+mod api {
+  use std::{borrow::Cow, ffi::{c_void, CStr, CString}};
+
+  use binmarshal::AsymmetricCow;
+  use grapple_frc_msgs::{capi::*, grapple::{device_info::GrappleDeviceInfo, GrappleBroadcastMessage, GrappleDeviceMessage, MaybeFragment}, ManufacturerMessage, Message};
+
+  fn encode(msg: *const GrplMessage) -> (u32, Vec<u8>) {
+    let (mut id, mut buf, mut len) = (0, [0u8; 64], 0);
+    assert_eq!(unsafe { grpl_message_encode(msg, &mut id, buf.as_mut_ptr(), buf.len(), &mut len) }, GrplStatus::Ok);
+    (id, buf[..len].to_vec())
+  }
+
+  fn decode(id: u32, data: &[u8]) -> *mut GrplMessage {
+    let mut out = core::ptr::null_mut();
+    assert_eq!(unsafe { grpl_message_decode(id, data.as_ptr(), data.len(), &mut out) }, GrplStatus::Ok);
+    out
+  }
+
+  fn to_json(msg: *const GrplMessage) -> String {
+    let mut len = 0;
+    assert_eq!(unsafe { grpl_message_to_json(msg, core::ptr::null_mut(), 0, &mut len) }, GrplStatus::BufferTooSmall);
+
+    let mut buf = vec![0 as core::ffi::c_char; len + 1];
+    assert_eq!(unsafe { grpl_message_to_json(msg, buf.as_mut_ptr(), buf.len(), &mut len) }, GrplStatus::Ok);
+    unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_owned()
+  }
+
+  fn from_json(json: &str) -> Result<*mut GrplMessage, GrplStatus> {
+    let json = CString::new(json).unwrap();
+    let mut out = core::ptr::null_mut();
+    match unsafe { grpl_message_from_json(json.as_ptr(), &mut out) } {
+      GrplStatus::Ok => Ok(out),
+      e => Err(e),
+    }
+  }
+
+  extern "C" fn collect(user: *mut c_void, id: u32, data: *const u8, len: usize) {
+    let frames = unsafe { &mut *(user as *mut Vec<(u32, Vec<u8>)>) };
+    frames.push((id, unsafe { core::slice::from_raw_parts(data, len) }.to_vec()));
+  }
+
+  #[test]
+  fn decode_encode() {
+    let msg = grpl_lasercan_set_timing_budget(3, 50);
+    let (id, data) = encode(msg);
+
+    let decoded = decode(id, &data);
+    assert_eq!(unsafe { grpl_message_id(decoded) }, id);
+    assert_eq!(encode(decoded), (id, data.clone()));
+
+    // A buffer that is too short still reports the length it needs
+    let (mut out_id, mut len) = (0, 0);
+    assert_eq!(unsafe { grpl_message_encode(msg, &mut out_id, core::ptr::null_mut(), 0, &mut len) }, GrplStatus::BufferTooSmall);
+    assert_eq!(len, data.len());
+
+    unsafe {
+      grpl_message_free(msg);
+      grpl_message_free(decoded);
+    }
+  }
+
+  #[test]
+  fn json() {
+    let msg = grpl_lasercan_set_roi(3, 8, 8, 16, 16);
+    let json = to_json(msg);
+    let parsed = from_json(&json).unwrap();
+    assert_eq!(encode(parsed), encode(msg));
+    assert_eq!(to_json(parsed), json);
+
+    assert_eq!(from_json("not json").err(), Some(GrplStatus::InvalidJson));
+    assert_eq!(unsafe { grpl_message_from_json(core::ptr::null(), &mut core::ptr::null_mut()) }, GrplStatus::NullPointer);
+
+    unsafe {
+      grpl_message_free(msg);
+      grpl_message_free(parsed);
+    }
+  }
+
+  #[test]
+  fn reassembler() {
+    let set_name = Message::new(4, ManufacturerMessage::Grapple(MaybeFragment::Message(GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::SetName {
+      serial: 0x1234, name: AsymmetricCow(Cow::Borrowed("A much longer device name"))
+    })))));
+    let msg = from_json(&serde_json::to_string(&set_name).unwrap()).unwrap();
+    let reassembler = grpl_reassembler_new(1000, 8);
+
+    let mut frames: Vec<(u32, Vec<u8>)> = vec![];
+    assert_eq!(unsafe { grpl_reassembler_fragment(reassembler, msg, Some(collect), &mut frames as *mut _ as *mut c_void) }, GrplStatus::Ok);
+    assert!(frames.len() > 1);
+    assert_eq!(unsafe { grpl_reassembler_fragment(reassembler, msg, None, core::ptr::null_mut()) }, GrplStatus::NullPointer);
+
+    // Every fragment but the last leaves the output empty
+    let mut out = core::ptr::null_mut();
+    for (i, (id, data)) in frames.iter().enumerate() {
+      assert_eq!(unsafe { grpl_reassembler_defragment(reassembler, 0, *id, data.as_ptr(), data.len(), &mut out) }, GrplStatus::Ok);
+      assert_eq!(out.is_null(), i + 1 < frames.len());
+    }
+    assert_eq!(to_json(out), to_json(msg));
+
+    unsafe {
+      grpl_message_free(msg);
+      grpl_message_free(out);
+      grpl_reassembler_free(reassembler);
+    }
+  }
+}
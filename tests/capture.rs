@@ -0,0 +1,415 @@
+#![cfg(feature = "std")]
+
+use std::borrow::Cow;
+
+use binmarshal::AsymmetricCow;
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{
+  can::CanFrameFormat,
+  capture::{
+    candump::{parse_hex, parse_line, CandumpEntry, CandumpReader, CandumpWriter, format_line},
+    pcap::{PcapReader, PcapRecord, PcapngWriter, LINKTYPE_CAN_SOCKETCAN},
+    CaptureError,
+  },
+  grapple::{misc::MiscMessage, GrappleDeviceMessage, MaybeFragment},
+  transport::CanFrame,
+  ManufacturerMessage, Message, MessageId,
+};
+
+fn misc(device_id: u8, payload: &[u8]) -> Message<'static> {
+  let msg = GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(AsymmetricCow(Cow::Borrowed(payload.into()))));
+  Message::new(device_id, ManufacturerMessage::Grapple(MaybeFragment::Message(msg))).to_static()
+}
+
+fn assert_malformed<T: std::fmt::Debug>(result: Result<T, CaptureError>) {
+  assert!(matches!(result, Err(CaptureError::Malformed(_))), "{:?}", result);
+}
+
+/* CANDUMP */
+
+#[test]
+fn hex() {
+  assert!(parse_hex("").unwrap().is_empty());
+  assert_eq!(parse_hex("00deADbeEF").unwrap(), [0x00, 0xDE, 0xAD, 0xBE, 0xEF]);
+
+  for s in ["0", "0G", "+1", "-1", " 1", "é", "aé", "éa", "aéb0", "ab\u{1F600}"] {
+    assert_malformed(parse_hex(s));
+  }
+}
+
+#[test]
+fn candump_parse() {
+  let entry = parse_line("(1436509052.249713) can0 0C0180C1#DEADBEEF").unwrap();
+  assert_eq!(entry, CandumpEntry {
+    interface: "can0".to_owned(),
+    format: CanFrameFormat::Classic,
+    fd_flags: 0,
+    frame: CanFrame::new(MessageId::from(0x0C0180C1), 1_436_509_052_249_713, &[0xDE, 0xAD, 0xBE, 0xEF]),
+  });
+
+  let entry = parse_line("(12.5) can1 0C0180C1##1000102030405060708").unwrap();
+  assert_eq!(entry.format, CanFrameFormat::Fd);
+  assert_eq!(entry.fd_flags, 1);
+  assert_eq!(entry.frame.timestamp, 12_500_000);
+  assert_eq!(&entry.frame.data[..], &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+  assert_eq!(&parse_line("(0.0) can0 0C0180C1#0102_9").unwrap().frame.data[..], &[1, 2]);
+  assert_eq!(parse_line("(0.0) can0 0C0180C1#").unwrap().frame.data.len(), 0);
+}
+
+#[test]
+fn candump_malformed() {
+  for line in [
+    "",
+    "can0 0C0180C1#00",
+    "(1.0) can0",
+    "1.0 can0 0C0180C1#00",
+    "(1.0) can0 0C0180C1",
+    "(1.0) can0 0C0180C1#0",
+    "(1.0) can0 0C0180C1#0G",
+    "(1.0) can0 0C0180C1#+1",
+    "(1.0) can0 0C0180CZ#00",
+    "(1.0) can0 0C0180C1#000102030405060708",
+    "(1.0) can0 0C0180C1##Z00",
+    "(x.0) can0 0C0180C1#00",
+    "(-1.0) can0 0C0180C1#00",
+    "(1.) can0 0C0180C1#00",
+    "(1.-5) can0 0C0180C1#00",
+    // Overflows the timestamp in microseconds
+    "(9223372036854775807.0) can0 0C0180C1#00",
+    "(9223372036854.775808) can0 0C0180C1#00",
+  ] {
+    assert_malformed(parse_line(line));
+  }
+}
+
+#[test]
+fn candump_non_ascii() {
+  for line in [
+    "(1.0) can0 0C0180C1#aéb0",
+    "(1.0) can0 0C0180C1#éé",
+    "(1.0) can0 0C0180C1##é00",
+    "(1.0) can0 0C0180C1##1é0",
+    "(1.é) can0 0C0180C1#00",
+    "(1.0) can0 0C0180é#00",
+  ] {
+    assert_malformed(parse_line(line));
+  }
+}
+
+#[test]
+fn candump_unsupported() {
+  for line in [
+    "(1.0) can0 123#00",
+    "(1.0) can0 0C0180C1#R",
+    "(1.0) can0 20000080#0000000000000000",
+  ] {
+    assert!(matches!(parse_line(line), Err(CaptureError::Unsupported(_))), "{}", line);
+  }
+
+  let log = "(1.0) can0 20000080#0000000000000000\n(2.0) can0 123#00\n\n(3.0) can0 0C0180C1#01\n";
+  let mut reader = CandumpReader::new(log.as_bytes(), 1_000_000);
+  let entry = reader.next_entry().unwrap().unwrap();
+  assert_eq!(entry.frame.timestamp, 3_000_000);
+  assert!(reader.next_entry().unwrap().is_none());
+}
+
+#[test]
+fn candump_format_roundtrip() {
+  for entry in [
+    CandumpEntry { interface: "can0".to_owned(), format: CanFrameFormat::Classic, fd_flags: 0, frame: CanFrame::new(MessageId::from(0x1FFFFFFF), 0, &[]) },
+    CandumpEntry { interface: "vcan3".to_owned(), format: CanFrameFormat::Classic, fd_flags: 0, frame: CanFrame::new(MessageId::from(0x0C0180C1), 1_000_001, &[0xFF; 8]) },
+    CandumpEntry { interface: "can1".to_owned(), format: CanFrameFormat::Fd, fd_flags: 3, frame: CanFrame::new(MessageId::from(0x0606_4401), 1_436_509_052_249_713, &[0xA5; 64]) },
+  ] {
+    assert_eq!(parse_line(&format_line(&entry)).unwrap(), entry);
+  }
+}
+
+#[test]
+fn candump_writer_roundtrip() {
+  for format in [CanFrameFormat::Classic, CanFrameFormat::Fd] {
+    let messages = [
+      (1_000_000, misc(1, &[1, 2, 3])),
+      (2_500_000, misc(2, &[0xAA; 100])),
+      (3_000_001, misc(3, &[])),
+    ];
+
+    let mut writer = CandumpWriter::new(vec![], "can0", format);
+    for (ts, msg) in &messages {
+      writer.write_message(*ts, msg.clone()).unwrap();
+    }
+    let log = writer.into_inner();
+
+    let mut reader = CandumpReader::new(&log[..], 1_000_000);
+    for (ts, msg) in &messages {
+      assert_eq!(reader.next_message().unwrap(), Some((*ts, msg.clone())));
+    }
+    assert!(reader.next_message().unwrap().is_none());
+  }
+}
+
+/* PCAP */
+
+// A SocketCAN packet as it appears in a capture
+fn socketcan(id: u32, data: &[u8]) -> Vec<u8> {
+  let mut out = (id | 0x8000_0000).to_be_bytes().to_vec();
+  out.extend_from_slice(&[data.len() as u8, 0, 0, 0]);
+  out.extend_from_slice(data);
+  out
+}
+
+fn pcap_header(linktype: u32) -> Vec<u8> {
+  let mut out = vec![];
+  out.extend_from_slice(&0xA1B2_C3D4u32.to_le_bytes());
+  out.extend_from_slice(&2u16.to_le_bytes());
+  out.extend_from_slice(&4u16.to_le_bytes());
+  out.extend_from_slice(&[0; 8]);
+  out.extend_from_slice(&65535u32.to_le_bytes());
+  out.extend_from_slice(&linktype.to_le_bytes());
+  out
+}
+
+fn pcap_record(out: &mut Vec<u8>, secs: u32, micros: u32, packet: &[u8]) {
+  for v in [secs, micros, packet.len() as u32, packet.len() as u32] {
+    out.extend_from_slice(&v.to_le_bytes());
+  }
+  out.extend_from_slice(packet);
+}
+
+fn pcapng_block(out: &mut Vec<u8>, block_type: u32, body: &[u8]) {
+  let total_len = 12 + body.len() as u32;
+  out.extend_from_slice(&block_type.to_le_bytes());
+  out.extend_from_slice(&total_len.to_le_bytes());
+  out.extend_from_slice(body);
+  out.extend_from_slice(&total_len.to_le_bytes());
+}
+
+// An interface description block, optionally with an if_tsresol option
+fn pcapng_idb(out: &mut Vec<u8>, tsresol: Option<u8>) {
+  let mut body = vec![];
+  body.extend_from_slice(&LINKTYPE_CAN_SOCKETCAN.to_le_bytes());
+  body.extend_from_slice(&[0; 2]);
+  body.extend_from_slice(&72u32.to_le_bytes());
+  if let Some(v) = tsresol {
+    body.extend_from_slice(&9u16.to_le_bytes());
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&[v, 0, 0, 0]);
+    body.extend_from_slice(&[0; 4]);
+  }
+  pcapng_block(out, 1, &body);
+}
+
+fn pcapng_epb(out: &mut Vec<u8>, interface: u32, ts: u64, packet: &[u8]) {
+  let mut body = vec![];
+  for v in [interface, (ts >> 32) as u32, ts as u32, packet.len() as u32, packet.len() as u32] {
+    body.extend_from_slice(&v.to_le_bytes());
+  }
+  body.extend_from_slice(packet);
+  body.resize((body.len() + 3) & !3, 0);
+  pcapng_block(out, 6, &body);
+}
+
+// A pcapng capture holding just a section header
+fn pcapng_header() -> Vec<u8> {
+  PcapngWriter::new(vec![], CanFrameFormat::Classic).unwrap().into_inner()
+}
+
+fn read_all(capture: &[u8]) -> Result<Vec<PcapRecord>, CaptureError> {
+  let mut reader = PcapReader::new(capture, 1_000_000)?;
+  let mut records = vec![];
+  while let Some(record) = reader.next_record()? {
+    records.push(record);
+  }
+  Ok(records)
+}
+
+#[test]
+fn pcapng_writer_roundtrip() {
+  for format in [CanFrameFormat::Classic, CanFrameFormat::Fd] {
+    let messages = [
+      (0, 1_000_000, misc(1, &[1, 2, 3])),
+      (2, 1_500_000, misc(1, &[0xAA; 100])),
+      // A whole CAN FD step, since misc payloads would otherwise pick up the padding
+      (1, 2_000_000, misc(1, &[0x55; 48])),
+      (0, 5_000_000_000, misc(4, &[])),
+    ];
+
+    let mut writer = PcapngWriter::new(vec![], format).unwrap();
+    for (channel, ts, msg) in &messages {
+      writer.write_message(*channel, *ts, msg.clone()).unwrap();
+    }
+    let capture = writer.into_inner();
+
+    let mut reader = PcapReader::new(&capture[..], 1_000_000).unwrap();
+    for (channel, ts, msg) in &messages {
+      assert_eq!(reader.next_message().unwrap(), Some((*channel, *ts, msg.clone())));
+    }
+    assert!(reader.next_message().unwrap().is_none());
+  }
+}
+
+#[test]
+fn pcapng_frames_roundtrip() {
+  let frames = [
+    (0, CanFrame::new(MessageId::from(0x0C0180C1), 0, &[])),
+    (1, CanFrame::new(MessageId::from(0x1FFFFFFF), 1, &[0xFF; 64])),
+    (0, CanFrame::new(MessageId::from(0x0606_4401), 1_436_509_052_249_713, &[1, 2, 3])),
+  ];
+
+  let mut writer = PcapngWriter::new(vec![], CanFrameFormat::Fd).unwrap();
+  for (channel, frame) in &frames {
+    writer.write_frame(*channel, frame).unwrap();
+  }
+
+  let records = read_all(&writer.into_inner()).unwrap();
+  assert_eq!(records.len(), frames.len());
+  for (record, (channel, frame)) in records.iter().zip(&frames) {
+    assert_eq!(record.channel, *channel);
+    assert_eq!(&record.frame, frame);
+  }
+}
+
+#[test]
+fn pcapng_writer_padding() {
+  let mut writer = PcapngWriter::new(vec![], CanFrameFormat::Fd).unwrap();
+  writer.write_frame(0, &CanFrame::new(MessageId::from(0x0C0180C1), 0, &[0xFF; 13])).unwrap();
+  writer.write_frame(0, &CanFrame::new(MessageId::from(0x0C0180C1), 0, &[0xFF; 8])).unwrap();
+
+  let records = read_all(&writer.into_inner()).unwrap();
+  let mut padded = vec![0xFF; 13];
+  padded.resize(16, 0);
+  assert_eq!(&records[0].frame.data[..], &padded[..]);
+  assert_eq!(&records[1].frame.data[..], &[0xFF; 8]);
+
+  // Frames too long for the bus are rejected rather than truncated
+  let mut writer = PcapngWriter::new(vec![], CanFrameFormat::Classic).unwrap();
+  assert_malformed(writer.write_frame(0, &CanFrame::new(MessageId::from(0x0C0180C1), 0, &[0; 9])));
+  let mut writer = PcapngWriter::new(vec![], CanFrameFormat::Fd).unwrap();
+  assert_malformed(writer.write_frame(0, &CanFrame::new(MessageId::from(0x0C0180C1), 0, &[0; 65])));
+}
+
+#[test]
+fn pcapng_timestamp_resolution() {
+  let mut capture = pcapng_header();
+  pcapng_idb(&mut capture, Some(9));
+  pcapng_idb(&mut capture, Some(0x80 | 10));
+  pcapng_epb(&mut capture, 0, 1_500_000_000, &socketcan(0x0C0180C1, &[1]));
+  pcapng_epb(&mut capture, 1, 3 * 1024, &socketcan(0x0C0180C1, &[2]));
+
+  let records = read_all(&capture).unwrap();
+  assert_eq!(records.iter().map(|r| r.frame.timestamp).collect::<Vec<_>>(), vec![1_500_000, 3_000_000]);
+
+  // Timestamps past i64::MAX microseconds saturate
+  let mut capture = pcapng_header();
+  pcapng_idb(&mut capture, Some(0));
+  pcapng_epb(&mut capture, 0, u64::MAX, &socketcan(0x0C0180C1, &[1]));
+  assert_eq!(read_all(&capture).unwrap()[0].frame.timestamp, i64::MAX);
+}
+
+#[test]
+fn pcap_reader() {
+  let mut capture = pcap_header(LINKTYPE_CAN_SOCKETCAN as u32);
+  pcap_record(&mut capture, 1, 250_000, &socketcan(0x0C0180C1, &[0xDE, 0xAD]));
+  // Standard ID, skipped
+  pcap_record(&mut capture, 2, 0, &[0x00, 0x00, 0x01, 0x23, 0x01, 0, 0, 0, 0xFF]);
+  pcap_record(&mut capture, 3, 5, &socketcan(0x1FFFFFFF, &[0xFF; 8]));
+
+  let records = read_all(&capture).unwrap();
+  assert_eq!(records, vec![
+    PcapRecord { channel: 0, format: CanFrameFormat::Classic, frame: CanFrame::new(MessageId::from(0x0C0180C1), 1_250_000, &[0xDE, 0xAD]) },
+    PcapRecord { channel: 0, format: CanFrameFormat::Classic, frame: CanFrame::new(MessageId::from(0x1FFFFFFF), 3_000_005, &[0xFF; 8]) },
+  ]);
+
+  // Other link types are skipped entirely
+  let mut capture = pcap_header(1);
+  pcap_record(&mut capture, 1, 0, &socketcan(0x0C0180C1, &[1]));
+  assert!(read_all(&capture).unwrap().is_empty());
+}
+
+#[test]
+fn pcap_malformed() {
+  assert_malformed(read_all(&[0xDE, 0xAD, 0xBE, 0xEF, 0, 0, 0, 0]));
+
+  // A record claiming to be 4 GiB
+  let mut capture = pcap_header(LINKTYPE_CAN_SOCKETCAN as u32);
+  for v in [1u32, 0, u32::MAX, u32::MAX] {
+    capture.extend_from_slice(&v.to_le_bytes());
+  }
+  assert_malformed(read_all(&capture));
+
+  // Truncated mid-record
+  let mut capture = pcap_header(LINKTYPE_CAN_SOCKETCAN as u32);
+  pcap_record(&mut capture, 1, 0, &socketcan(0x0C0180C1, &[1, 2, 3]));
+  capture.truncate(capture.len() - 2);
+  assert!(read_all(&capture).is_err());
+
+  // SocketCAN payload longer than the record
+  let mut capture = pcap_header(LINKTYPE_CAN_SOCKETCAN as u32);
+  let mut packet = socketcan(0x0C0180C1, &[1, 2, 3]);
+  packet[4] = 8;
+  pcap_record(&mut capture, 1, 0, &packet);
+  assert_malformed(read_all(&capture));
+
+  // SocketCAN lengths past CAN FD's 64 bytes, even with the payload present
+  for len in [65, 0xFF] {
+    let mut capture = pcap_header(LINKTYPE_CAN_SOCKETCAN as u32);
+    let mut packet = socketcan(0x0C0180C1, &[0; 0xFF]);
+    packet[4] = len;
+    pcap_record(&mut capture, 1, 0, &packet);
+    assert_malformed(read_all(&capture));
+  }
+}
+
+#[test]
+fn pcapng_malformed() {
+  // Section header lengths that are too short, or absurdly long
+  for len in [0u32, 8, 11, u32::MAX] {
+    let mut capture = pcapng_header();
+    capture[4..8].copy_from_slice(&len.to_le_bytes());
+    assert_malformed(read_all(&capture));
+
+    let mut capture = pcapng_header();
+    capture.extend_from_slice(&pcapng_header());
+    let n = capture.len() / 2;
+    capture[n + 4..n + 8].copy_from_slice(&len.to_le_bytes());
+    assert_malformed(read_all(&capture));
+  }
+
+  // Other blocks with bad lengths
+  for len in [0u32, 4, 11, 0x8000_0000, u32::MAX] {
+    let mut capture = pcapng_header();
+    capture.extend_from_slice(&6u32.to_le_bytes());
+    capture.extend_from_slice(&len.to_le_bytes());
+    capture.extend_from_slice(&[0; 16]);
+    assert_malformed(read_all(&capture));
+  }
+
+  // Timestamp resolutions that don't fit in 64 bits
+  for tsresol in [20, 0x7F, 0x80 | 64, 0xFF] {
+    let mut capture = pcapng_header();
+    pcapng_idb(&mut capture, Some(tsresol));
+    assert_malformed(read_all(&capture));
+  }
+
+  // Packet on an interface that was never described
+  let mut capture = pcapng_header();
+  pcapng_idb(&mut capture, None);
+  pcapng_epb(&mut capture, 1, 0, &socketcan(0x0C0180C1, &[1]));
+  assert_malformed(read_all(&capture));
+
+  // Interfaces past 255 have no channel to map to
+  let mut capture = pcapng_header();
+  for _ in 0..257 {
+    pcapng_idb(&mut capture, None);
+  }
+  pcapng_epb(&mut capture, 256, 0, &socketcan(0x0C0180C1, &[1]));
+  assert_malformed(read_all(&capture));
+
+  // Captured length past the end of the block
+  let mut capture = pcapng_header();
+  pcapng_idb(&mut capture, None);
+  let n = capture.len();
+  pcapng_epb(&mut capture, 0, 0, &socketcan(0x0C0180C1, &[1]));
+  capture[n + 8 + 12..n + 8 + 16].copy_from_slice(&u32::MAX.to_le_bytes());
+  assert_malformed(read_all(&capture));
+}
@@ -0,0 +1,85 @@
+// Decoding arbitrary bus traffic must never panic. The fuzz targets under fuzz/ explore this far more
+// thoroughly, this is a cheap check that runs with the rest of the tests.
+
+use binmarshal::{Marshal, MarshalError, VecBitWriter};
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{grapple::{fragments::FragmentReassembler, MANUFACTURER_GRAPPLE}, transport::MessageDecoder, ManufacturerMessage, MessageId, Validate};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const FRAMES: usize = 200_000;
+
+#[test]
+fn random_frames() {
+  let mut rng = StdRng::seed_from_u64(0);
+  let (mut rx, mut tx) = FragmentReassembler::new(100, 8).split();
+
+  for i in 0..FRAMES {
+    let mut id = MessageId::from(rng.gen_range(0..(1u32 << 29)));
+    // Most traffic of interest is ours, and a narrow range of device types / IDs makes fragments collide
+    if rng.gen_ratio(3, 4) {
+      id.manufacturer = MANUFACTURER_GRAPPLE;
+      id.device_id = rng.gen_range(0..4);
+    }
+
+    let len = if rng.gen() { rng.gen_range(0..=8) } else { rng.gen_range(0..=64) };
+    let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+    let msg = match MessageDecoder::decode_raw(id, &data[..]) {
+      Ok(msg) => msg,
+      Err(_) => continue,
+    };
+
+    let _ = msg.validate();
+    let _ = format!("{:?}", msg);
+    let mut writer = VecBitWriter::new();
+    let _ = msg.write(&mut writer, ());
+
+    if let ManufacturerMessage::Grapple(grpl) = msg.msg {
+      let mut storage = vec![];
+      if let Ok(Some((gid, m))) = rx.defragment(i as i64, &id, grpl, &mut storage) {
+        let _ = tx.maybe_fragment(gid.device_id, m.to_static(), &mut |_, _| {});
+      }
+    }
+  }
+}
+
+#[test]
+fn lasercan_roi_out_of_range() {
+  use grapple_frc_msgs::grapple::lasercan::LaserCanRoiU4;
+
+  for v in [0u8, 17, 255] {
+    assert!(LaserCanRoiU4(v).write(&mut VecBitWriter::new(), ()).is_err());
+  }
+}
+
+#[test]
+fn fragment_size_too_small() {
+  use grapple_frc_msgs::grapple::{GrappleDeviceMessage, misc::MiscMessage};
+
+  let (_, mut tx) = FragmentReassembler::new(100, 3).split();
+  let msg = GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(binmarshal::AsymmetricCow(std::borrow::Cow::Borrowed((&[0u8; 16][..]).into()))));
+  assert!(tx.maybe_fragment(1, msg, &mut |_, _| {}).is_err());
+}
+
+#[test]
+fn message_too_large() {
+  use grapple_frc_msgs::grapple::{GrappleDeviceMessage, fragments::MAX_EXTENDED_LEN, misc::MiscMessage};
+
+  let payload = vec![0u8; MAX_EXTENDED_LEN + 1];
+  let (_, mut tx) = FragmentReassembler::new(100, 8).split();
+  let msg = GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(binmarshal::AsymmetricCow(std::borrow::Cow::Borrowed((&payload[..]).into()))));
+  assert!(tx.maybe_fragment(1, msg, &mut |_, _| {}).is_err());
+
+  // Too long for the standard format, and too small a frame for the extended one
+  let (_, mut tx) = FragmentReassembler::new(100, 6).split();
+  let msg = GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(binmarshal::AsymmetricCow(std::borrow::Cow::Borrowed((&payload[..300]).into()))));
+  assert!(tx.maybe_fragment(1, msg, &mut |_, _| {}).is_err());
+}
+
+#[test]
+fn invalid_utf8() {
+  // LaserCAN SetRange Ack carrying GrappleError::Generic("\xFF")
+  let id = MessageId { device_type: 6, manufacturer: MANUFACTURER_GRAPPLE, api_class: 0b010001, api_index: 0, device_id: 1 };
+  assert_eq!(MessageDecoder::decode_raw(id, &[0x01, 0xFF, 0xFF, 0x00]), Err(MarshalError::CoercionError));
+  assert!(MessageDecoder::decode_raw(id, &[0x01, 0xFF, b'o', b'k', 0x00]).is_ok());
+}
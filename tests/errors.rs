@@ -0,0 +1,59 @@
+use std::borrow::Cow;
+
+use binmarshal::{AsymmetricCow, BitView, BitWriter, Demarshal, Marshal, VecBitWriter};
+use grapple_frc_msgs::grapple::errors::{GrappleError, GrappleErrorKind, GrappleResult, ParameterRange};
+
+fn text(s: &'static str) -> AsymmetricCow<'static, str> {
+  AsymmetricCow(Cow::Borrowed(s))
+}
+
+// One of every variant, in error code order
+fn every_error() -> Vec<GrappleError<'static>> {
+  vec![
+    GrappleError::ParameterOutOfBounds(text("bounds")),
+    GrappleError::FailedAssertion(text("assertion")),
+    GrappleError::ParameterOutOfRange(ParameterRange { index: 2, min: -100, max: 100 }),
+    GrappleError::Unsupported,
+    GrappleError::Busy,
+    GrappleError::InvalidState,
+    GrappleError::NotFound,
+    GrappleError::FlashError(0x42),
+    GrappleError::TimedOut(text("timeout")),
+    GrappleError::Generic(text("generic")),
+  ]
+}
+
+#[test]
+fn error_codes() {
+  let errors = every_error();
+  let codes: Vec<_> = errors.iter().map(|e| e.to_error_code()).collect();
+  assert_eq!(codes, [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0xFE, 0xFF]);
+
+  for err in errors {
+    // The error code is the marshal tag, so the first byte on the wire
+    let mut writer = VecBitWriter::new();
+    err.write(&mut writer, ()).unwrap();
+    assert_eq!(writer.slice()[0], err.to_error_code(), "{:?}", err);
+    assert_eq!(GrappleError::read(&mut BitView::new(writer.slice()), ()).unwrap(), err);
+    assert_eq!(GrappleErrorKind::from_error_code(err.to_error_code()), Some(err.kind()), "{:?}", err);
+  }
+
+  assert_eq!(GrappleErrorKind::from_error_code(0x08), None);
+  assert_eq!(GrappleError::from(binmarshal::MarshalError::IllegalTag).kind(), GrappleErrorKind::Generic);
+}
+
+#[test]
+fn error_conversions() {
+  fn is_error<E: std::error::Error + Send + Sync + 'static>() {}
+  is_error::<GrappleError<'static>>();
+
+  fn busy() -> anyhow::Result<()> {
+    Err(GrappleError::Busy)?
+  }
+  let err = busy().unwrap_err();
+  assert_eq!(err.to_string(), "Device Busy");
+  assert_eq!(err.downcast_ref::<GrappleError>(), Some(&GrappleError::Busy));
+
+  let parsed: GrappleResult<u8> = "x".parse::<u8>().map_err(GrappleError::generic);
+  assert_eq!(parsed, Err(GrappleError::Generic(AsymmetricCow(Cow::Owned("invalid digit found in string".to_owned())))));
+}
@@ -0,0 +1,271 @@
+#![cfg(all(feature = "serde", feature = "ni", feature = "grapple_lasercan", feature = "grapple_mitocandria", feature = "grapple_flexican", feature = "grapple_jms", feature = "firmware_update_v1"))]
+
+// Golden wire-format vectors. tests/vectors/messages.json records the exact CAN IDs and payload bytes
+// that deployed firmware sends and expects for each message. These vectors pin the wire format, so
+// never regenerate them to make a failing test pass. A failure here means the encoding has changed,
+// most likely because of a binmarshal bump or a change to a marshal attribute.
+
+use std::collections::BTreeSet;
+
+use grapple_frc_msgs::{
+  grapple::{
+    device_info::GrappleDeviceInfo,
+    encapsulation::BridgeMessages,
+    firmware::GrappleFirmwareMessage,
+    flexican::FlexiCANMessage,
+    fragments::{FragmentReassembler, MAX_EXTENDED_LEN},
+    jms::{JMSCardUpdate, JMSMessage},
+    lasercan::LaserCanMessage,
+    misc::MiscMessage,
+    mitocandria::{MitocandriaChannelRequest, MitocandriaMessage},
+    GrappleBroadcastMessage, GrappleDeviceMessage, MaybeFragment, Request,
+  },
+  ni::{NiDeviceMessage, NiRioHeartbeat, NiRobotControllerMessage},
+  transport::{encode_message, CanFrame, MessageDecoder},
+  ManufacturerMessage, Message, MessageId,
+};
+use binmarshal::MarshalError;
+use serde::Deserialize;
+
+const VECTORS: &str = include_str!("vectors/messages.json");
+
+#[derive(Deserialize)]
+struct Frame {
+  id: String,
+  data: String,
+}
+
+#[derive(Deserialize)]
+struct Vector<'a> {
+  name: String,
+  frames: Vec<Frame>,
+  #[serde(borrow)]
+  message: Message<'a>,
+}
+
+fn vectors() -> Vec<Vector<'static>> {
+  serde_json::from_str(VECTORS).expect("malformed vector file")
+}
+
+fn parse_frame(frame: &Frame) -> (u32, Vec<u8>) {
+  let id = u32::from_str_radix(frame.id.trim_start_matches("0x"), 16).expect("bad id");
+  let data = (0..frame.data.len()).step_by(2)
+    .map(|i| u8::from_str_radix(&frame.data[i..i + 2], 16).expect("bad data"))
+    .collect();
+  (id, data)
+}
+
+fn request<R, A>(family: &'static str, req: &Request<R, A>) -> String {
+  match req {
+    Request::Request(_) => format!("{}/request", family),
+    Request::Ack(_) => format!("{}/ack", family),
+  }
+}
+
+// Names the variant a message exercises. This match is deliberately exhaustive so that adding a
+// message variant fails to compile until it is given a name here, in ALL_VARIANTS, and a vector.
+fn variant(msg: &Message) -> String {
+  match &msg.msg {
+    ManufacturerMessage::Ni(NiDeviceMessage::RobotController(NiRobotControllerMessage::Heartbeat(NiRioHeartbeat::Hearbeat(_)))) => "ni/rio_heartbeat".to_owned(),
+    ManufacturerMessage::Grapple(MaybeFragment::Fragment(_)) => panic!("vectors must hold reassembled messages"),
+    ManufacturerMessage::Grapple(MaybeFragment::Message(msg)) => match msg {
+      GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(di)) => match di {
+        GrappleDeviceInfo::EnumerateRequest => "device_info/enumerate_request",
+        GrappleDeviceInfo::EnumerateResponse { .. } => "device_info/enumerate_response",
+        GrappleDeviceInfo::Blink { .. } => "device_info/blink",
+        GrappleDeviceInfo::SetName { .. } => "device_info/set_name",
+        GrappleDeviceInfo::CommitConfig { .. } => "device_info/commit_config",
+        GrappleDeviceInfo::SetId { .. } => "device_info/set_id",
+        GrappleDeviceInfo::ArbitrationRequest => "device_info/arbitration_request",
+        GrappleDeviceInfo::ArbitrationReject => "device_info/arbitration_reject",
+      }.to_owned(),
+      GrappleDeviceMessage::FirmwareUpdate(fw) => match fw {
+        GrappleFirmwareMessage::StartFieldUpgrade { .. } => "firmware/start_field_upgrade".to_owned(),
+        GrappleFirmwareMessage::UpdatePart(_) => "firmware/update_part".to_owned(),
+        GrappleFirmwareMessage::UpdatePartAck => "firmware/update_part_ack".to_owned(),
+        GrappleFirmwareMessage::UpdateDone => "firmware/update_done".to_owned(),
+        GrappleFirmwareMessage::UpdatePartV2(req) => request("firmware/update_part_v2", req),
+        GrappleFirmwareMessage::GetFlashParameters(req) => request("firmware/get_flash_parameters", req),
+      },
+      GrappleDeviceMessage::DistanceSensor(lc) => match lc {
+        LaserCanMessage::Measurement(_) => "lasercan/measurement".to_owned(),
+        LaserCanMessage::SetRange(req) => request("lasercan/set_range", req),
+        LaserCanMessage::SetRoi(req) => request("lasercan/set_roi", req),
+        LaserCanMessage::SetTimingBudget(req) => request("lasercan/set_timing_budget", req),
+        LaserCanMessage::SetLedThreshold(req) => request("lasercan/set_led_threshold", req),
+      },
+      GrappleDeviceMessage::PowerDistributionModule(pdm) => match pdm {
+        MitocandriaMessage::StatusFrame(_) => "mitocandria/status_frame".to_owned(),
+        MitocandriaMessage::ChannelRequest(req) => match req {
+          MitocandriaChannelRequest::SetSwitchableChannel(req) => request("mitocandria/set_switchable_channel", req),
+          MitocandriaChannelRequest::SetAdjustableChannel(req) => request("mitocandria/set_adjustable_channel", req),
+          MitocandriaChannelRequest::CalibrateAdjChannel(req) => request("mitocandria/calibrate_adj_channel", req),
+          MitocandriaChannelRequest::StartAutoCalibrate(req) => request("mitocandria/start_auto_calibrate", req),
+        },
+      },
+      GrappleDeviceMessage::IOBreakout(FlexiCANMessage::Bridge(bridge)) => match bridge {
+        BridgeMessages::GetChannelName(req) => request("flexican/get_channel_name", req),
+        BridgeMessages::StartBridge(req) => request("flexican/start_bridge", req),
+        BridgeMessages::StopBridge(req) => request("flexican/stop_bridge", req),
+        BridgeMessages::BridgeMessage(_) => "flexican/bridge_message".to_owned(),
+      },
+      GrappleDeviceMessage::Misc(misc) => match misc {
+        MiscMessage::MiscMessage(_) => "misc/misc_message",
+        MiscMessage::JMS(jms) => match jms {
+          JMSMessage::Status(_) => "jms/status",
+          JMSMessage::SetRole(_) => "jms/set_role",
+          JMSMessage::Update(update) => match update.update {
+            JMSCardUpdate::IO() => "jms/update/io",
+            JMSCardUpdate::Lighting { .. } => "jms/update/lighting",
+          },
+          JMSMessage::Blink => "jms/blink",
+        },
+      }.to_owned(),
+    },
+  }
+}
+
+const ALL_VARIANTS: &[&str] = &[
+  "device_info/enumerate_request", "device_info/enumerate_response", "device_info/blink", "device_info/set_name",
+  "device_info/commit_config", "device_info/set_id", "device_info/arbitration_request", "device_info/arbitration_reject",
+
+  "firmware/start_field_upgrade", "firmware/update_part", "firmware/update_part_ack", "firmware/update_done",
+  "firmware/update_part_v2/request", "firmware/update_part_v2/ack",
+  "firmware/get_flash_parameters/request", "firmware/get_flash_parameters/ack",
+
+  "lasercan/measurement",
+  "lasercan/set_range/request", "lasercan/set_range/ack",
+  "lasercan/set_roi/request", "lasercan/set_roi/ack",
+  "lasercan/set_timing_budget/request", "lasercan/set_timing_budget/ack",
+  "lasercan/set_led_threshold/request", "lasercan/set_led_threshold/ack",
+
+  "mitocandria/status_frame",
+  "mitocandria/set_switchable_channel/request", "mitocandria/set_switchable_channel/ack",
+  "mitocandria/set_adjustable_channel/request", "mitocandria/set_adjustable_channel/ack",
+  "mitocandria/calibrate_adj_channel/request", "mitocandria/calibrate_adj_channel/ack",
+  "mitocandria/start_auto_calibrate/request", "mitocandria/start_auto_calibrate/ack",
+
+  "flexican/get_channel_name/request", "flexican/get_channel_name/ack",
+  "flexican/start_bridge/request", "flexican/start_bridge/ack",
+  "flexican/stop_bridge/request", "flexican/stop_bridge/ack",
+  "flexican/bridge_message",
+
+  "misc/misc_message",
+  "jms/status", "jms/set_role", "jms/update/io", "jms/update/lighting", "jms/blink",
+
+  "ni/rio_heartbeat",
+];
+
+#[test]
+fn decode() {
+  for vector in vectors() {
+    let mut decoder = MessageDecoder::new(1000);
+    let n = vector.frames.len();
+
+    for (i, frame) in vector.frames.iter().enumerate() {
+      let (id, data) = parse_frame(frame);
+      let decoded = decoder.decode(&CanFrame::new(MessageId::from(id), i as i64, &data))
+        .unwrap_or_else(|e| panic!("{}: frame {} failed to decode: {:?}", vector.name, i, e));
+
+      if i + 1 < n {
+        assert!(decoded.is_none(), "{}: message completed early at frame {}", vector.name, i);
+      } else {
+        assert_eq!(decoded.as_ref(), Some(&vector.message), "{}: decoded message differs", vector.name);
+      }
+    }
+  }
+}
+
+#[test]
+fn encode() {
+  for vector in vectors() {
+    // A fresh transmitter always starts at fragment ID 1, which the vectors rely on
+    let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+    let mut frames = vec![];
+    encode_message(&mut tx, vector.message.clone(), &mut |id: MessageId, data: &[u8]| frames.push((u32::from(id), data.to_vec())))
+      .unwrap_or_else(|e| panic!("{}: failed to encode: {:?}", vector.name, e));
+
+    let expected: Vec<_> = vector.frames.iter().map(parse_frame).collect();
+    assert_eq!(frames, expected, "{}: encoded frames differ", vector.name);
+  }
+}
+
+#[test]
+fn every_variant_has_a_vector() {
+  let mut seen = BTreeSet::new();
+  for vector in vectors() {
+    let variant = variant(&vector.message);
+    assert!(vector.name.starts_with(&variant), "vector {} holds a {} message", vector.name, variant);
+    seen.insert(variant);
+  }
+
+  let missing: Vec<_> = ALL_VARIANTS.iter().filter(|v| !seen.contains(**v)).collect();
+  assert!(missing.is_empty(), "no golden vector for {:?}", missing);
+}
+
+// binmarshal doesn't check strings are valid UTF-8, so every message type implements ValidateUtf8 by
+// hand. This puts invalid UTF-8 into each string in each vector in turn, so a type whose impl misses
+// a string field fails here as soon as it has a vector.
+#[test]
+fn invalid_utf8_in_every_string() {
+  const MARKER: &str = "~UTF8~";
+
+  fn string_paths(value: &serde_json::Value, path: &mut Vec<serde_json::Value>, out: &mut Vec<Vec<serde_json::Value>>) {
+    match value {
+      serde_json::Value::String(_) => out.push(path.clone()),
+      serde_json::Value::Array(items) => for (i, item) in items.iter().enumerate() {
+        path.push(i.into());
+        string_paths(item, path, out);
+        path.pop();
+      },
+      // Enum tags aren't strings on the wire
+      serde_json::Value::Object(fields) => for (key, field) in fields.iter().filter(|(key, _)| *key != "type") {
+        path.push(key.clone().into());
+        string_paths(field, path, out);
+        path.pop();
+      },
+      _ => {},
+    }
+  }
+
+  fn lookup<'v>(value: &'v mut serde_json::Value, path: &[serde_json::Value]) -> &'v mut serde_json::Value {
+    path.iter().fold(value, |v, p| match p {
+      serde_json::Value::Number(i) => &mut v[i.as_u64().unwrap() as usize],
+      serde_json::Value::String(k) => &mut v[k.as_str()],
+      _ => unreachable!(),
+    })
+  }
+
+  let raw: Vec<serde_json::Value> = serde_json::from_str(VECTORS).unwrap();
+  let mut checked = 0;
+
+  for vector in raw {
+    let name = vector["name"].as_str().unwrap();
+    let mut paths = vec![];
+    string_paths(&vector["message"], &mut vec![], &mut paths);
+
+    for path in paths {
+      let mut message = vector["message"].clone();
+      *lookup(&mut message, &path) = MARKER.into();
+      let json = message.to_string();
+      // Strings that aren't free text, such as unit enum variants, don't take the marker
+      let Ok(message) = serde_json::from_str::<Message>(&json) else { continue };
+
+      // Encode as a single frame, however long, so the string isn't split across fragments
+      let (_, mut tx) = FragmentReassembler::new(1000, MAX_EXTENDED_LEN).split();
+      let mut frames = vec![];
+      encode_message(&mut tx, message, &mut |id: MessageId, data: &[u8]| frames.push((id, data.to_vec()))).unwrap();
+      let [(id, mut data)] = <[_; 1]>::try_from(frames).unwrap();
+
+      let at = data.windows(MARKER.len()).position(|w| w == MARKER.as_bytes())
+        .unwrap_or_else(|| panic!("{}: string at {:?} isn't on the wire", name, path));
+      assert!(MessageDecoder::decode_raw(id, &data).is_ok(), "{}", name);
+
+      data[at] = 0xFF;
+      assert_eq!(MessageDecoder::decode_raw(id, &data).err(), Some(MarshalError::CoercionError), "{}: invalid UTF-8 at {:?} accepted", name, path);
+      checked += 1;
+    }
+  }
+
+  assert!(checked > 0);
+}
@@ -0,0 +1,729 @@
+use std::borrow::Cow;
+
+use binmarshal::{AsymmetricCow, BitView, BitWriter, Demarshal, Marshal, MarshalUpdate, VecBitWriter};
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{
+  grapple::{
+    fragments::{DefragmentError, FixedFragmentReassemblerRx, Fragment, FragmentBody, FragmentDeviceKey, FragmentError, FragmentFrames, FragmentReassembler, FragmentReassemblerRx, FragmentReassemblerTx, FragmentRxStats, FragmentTxStats, ReassembledMessage},
+    device_info::GrappleDeviceInfo,
+    misc::MiscMessage,
+    GrappleBroadcastMessage, GrappleDeviceMessage, GrappleMessageId, MaybeFragment,
+  },
+  MessageId,
+};
+
+fn misc(payload: &[u8]) -> GrappleDeviceMessage<'static> {
+  GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(AsymmetricCow(Cow::Borrowed(payload.into()))).to_static())
+}
+
+fn fragment(tx: &mut FragmentReassemblerTx, msg: GrappleDeviceMessage<'static>) -> Vec<(MessageId, Vec<u8>)> {
+  fragment_from(tx, 1, msg)
+}
+
+fn fragment_from(tx: &mut FragmentReassemblerTx, device_id: u8, msg: GrappleDeviceMessage<'static>) -> Vec<(MessageId, Vec<u8>)> {
+  let mut frames = vec![];
+  tx.maybe_fragment(device_id, msg, &mut |id, data| frames.push((id, data.to_vec()))).unwrap();
+  frames
+}
+
+// Feeds frames through the reassembler, stopping at the first message or error
+fn defragment<'a>(rx: &mut FragmentReassemblerRx, frames: impl IntoIterator<Item = &'a (MessageId, Vec<u8>)>) -> Result<Option<GrappleDeviceMessage<'static>>, DefragmentError> {
+  defragment_at(rx, 0, frames)
+}
+
+fn defragment_at<'a>(rx: &mut FragmentReassemblerRx, now: i64, frames: impl IntoIterator<Item = &'a (MessageId, Vec<u8>)>) -> Result<Option<GrappleDeviceMessage<'static>>, DefragmentError> {
+  for (id, data) in frames {
+    let frag = MaybeFragment::read(&mut BitView::new(data), (*id).into())?;
+    let mut storage = vec![];
+    if let Some((_, msg)) = rx.defragment(now, id, frag, &mut storage)? {
+      return Ok(Some(msg.to_static()));
+    }
+  }
+  Ok(None)
+}
+
+fn defragment_fixed<'a, const T: usize, const N: usize>(rx: &mut FixedFragmentReassemblerRx<T, N>, now: i64, frames: impl IntoIterator<Item = &'a (MessageId, Vec<u8>)>) -> Result<Option<GrappleDeviceMessage<'static>>, DefragmentError> {
+  for (id, data) in frames {
+    let frag = MaybeFragment::read(&mut BitView::new(data), (*id).into())?;
+    if let Some((_, msg)) = rx.defragment(now, id, frag)? {
+      return Ok(Some(msg.to_static()));
+    }
+  }
+  Ok(None)
+}
+
+#[test]
+fn crc_roundtrip() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  tx.set_crc(true);
+
+  // CRC-16/CCITT-FALSE check value for "123456789" is 0x29B1
+  let frames = fragment(&mut tx, misc(b"123456789"));
+  assert_eq!(frames[0].1, [0x80, 0x00, 0x09, 0x29, 0xB1, b'1', b'2', b'3']);
+  assert_eq!(defragment(&mut rx, &frames), Ok(Some(misc(b"123456789"))));
+
+  // Extended fragments carry it too
+  let payload: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+  let frames = fragment(&mut tx, misc(&payload));
+  assert_eq!(defragment(&mut rx, &frames), Ok(Some(misc(&payload))));
+}
+
+#[test]
+fn crc_mismatch() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  tx.set_crc(true);
+
+  let mut frames = fragment(&mut tx, misc(b"123456789"));
+  frames[1].1[0] ^= 0xFF;
+  assert!(matches!(defragment(&mut rx, &frames), Err(DefragmentError::CrcMismatch { expected: 0x29B1, .. })));
+}
+
+#[test]
+fn stale_fragments() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+
+  // A fresh transmitter always starts at the same fragment ID, so these two transfers collide.
+  // The start of the first transfer was lost, and the rest of it is still waiting when the
+  // second, shorter transfer arrives.
+  let stale = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0xAA; 40]));
+  let fresh = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0x55; 12]));
+
+  assert_eq!(defragment(&mut rx, &stale[1..]), Ok(None));
+  assert_eq!(defragment(&mut rx, &fresh), Err(DefragmentError::Discontiguous));
+}
+
+#[test]
+fn new_start_discards_stale_fragments() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+
+  let stale = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0xAA; 40]));
+  let fresh = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0x55; 12]));
+
+  assert_eq!(defragment(&mut rx, &stale[..3]), Ok(None));
+  assert_eq!(defragment(&mut rx, &fresh), Ok(Some(misc(&[0x55; 12]))));
+}
+
+// Firmware keeps these in a static, so construction has to work in a const context
+static _FIXED: FixedFragmentReassemblerRx<4, 256> = FixedFragmentReassemblerRx::new(1000);
+
+#[test]
+fn fixed_evicts_stalest_transfer() {
+  let mut rx = FixedFragmentReassemblerRx::<2, 64>::new(1000);
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  let a = fragment_from(&mut tx, 1, misc(&[0x11; 20]));
+  let b = fragment_from(&mut tx, 2, misc(&[0x22; 20]));
+  let c = fragment_from(&mut tx, 3, misc(&[0x33; 20]));
+
+  assert_eq!(defragment_fixed(&mut rx, 0, &a[..1]), Ok(None));
+  assert_eq!(defragment_fixed(&mut rx, 1, &b[..1]), Ok(None));
+  // Both slots are busy, so this evicts a, which has been quiet the longest
+  assert_eq!(defragment_fixed(&mut rx, 2, &c[..1]), Ok(None));
+
+  assert_eq!(defragment_fixed(&mut rx, 3, &a[1..]), Ok(None));
+  assert_eq!(defragment_fixed(&mut rx, 3, &b[1..]), Ok(Some(misc(&[0x22; 20]))));
+  assert_eq!(defragment_fixed(&mut rx, 3, &c[1..]), Ok(Some(misc(&[0x33; 20]))));
+}
+
+#[test]
+fn fixed_rejects_oversized_messages() {
+  let mut rx = FixedFragmentReassemblerRx::<2, 16>::new(1000);
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  let frames = fragment(&mut tx, misc(&[0xAA; 40]));
+  assert_eq!(defragment_fixed(&mut rx, 0, &frames), Err(DefragmentError::Marshal(binmarshal::MarshalError::BufferTooSmall)));
+
+  let frames = fragment(&mut tx, misc(&[0xAA; 16]));
+  assert_eq!(defragment_fixed(&mut rx, 0, &frames), Ok(Some(misc(&[0xAA; 16]))));
+}
+
+#[test]
+fn fixed_requires_fragments_in_order() {
+  let mut rx = FixedFragmentReassemblerRx::<2, 64>::new(1000);
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  // Repeats are ignored
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+  let repeated = [&frames[0], &frames[1], &frames[1], &frames[2]];
+  assert_eq!(defragment_fixed(&mut rx, 0, repeated), Ok(Some(misc(&[0x55; 20]))));
+
+  // Gaps fail the transfer
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+  assert_eq!(defragment_fixed(&mut rx, 0, [&frames[0], &frames[2]]), Err(DefragmentError::Discontiguous));
+
+  // Without its start, the rest of the transfer is ignored
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+  assert_eq!(defragment_fixed(&mut rx, 0, &frames[1..]), Ok(None));
+}
+
+#[test]
+fn stats() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  // Completed, with a repeated fragment
+  let frames = fragment_from(&mut tx, 1, misc(&[0x11; 20]));
+  assert_eq!(defragment_at(&mut rx, 0, [&frames[0], &frames[1], &frames[1], &frames[2]]), Ok(Some(misc(&[0x11; 20]))));
+  // Failed
+  let (_, mut crc_tx) = FragmentReassembler::new(1000, 8).split();
+  crc_tx.set_crc(true);
+  let mut frames = fragment_from(&mut crc_tx, 2, misc(&[0x22; 20]));
+  frames[1].1[0] ^= 0xFF;
+  assert!(matches!(defragment_at(&mut rx, 0, &frames), Err(DefragmentError::CrcMismatch { .. })));
+  // Aged off
+  let frames = fragment_from(&mut tx, 2, misc(&[0x22; 20]));
+  assert_eq!(defragment_at(&mut rx, 0, &frames[..1]), Ok(None));
+  // In flight
+  let frames = fragment_from(&mut tx, 1, misc(&[0x11; 20]));
+  assert_eq!(defragment_at(&mut rx, 1001, &frames[..1]), Ok(None));
+  // Single frame messages aren't transfers
+  let frames = fragment_from(&mut tx, 1, misc(&[0x11; 2]));
+  assert_eq!(defragment_at(&mut rx, 1001, &frames), Ok(Some(misc(&[0x11; 2]))));
+
+  assert_eq!(rx.stats(), FragmentRxStats { started: 4, completed: 1, decode_failures: 1, aged_off: 1, abandoned: 0, in_flight: 1, duplicates: 1, nacks: 0 });
+  assert_eq!(rx.device_stats().into_iter().collect::<Vec<_>>(), vec![
+    (FragmentDeviceKey { channel: 0, device_type: 30, device_id: 1 }, FragmentRxStats { started: 2, completed: 1, in_flight: 1, duplicates: 1, ..Default::default() }),
+    (FragmentDeviceKey { channel: 0, device_type: 30, device_id: 2 }, FragmentRxStats { started: 2, decode_failures: 1, aged_off: 1, ..Default::default() }),
+  ]);
+
+  rx.prune(2002);
+  assert_eq!(rx.stats(), FragmentRxStats { started: 4, completed: 1, decode_failures: 1, aged_off: 2, abandoned: 0, in_flight: 0, duplicates: 1, nacks: 0 });
+
+  assert_eq!(tx.stats(), &FragmentTxStats { messages: 4, fragmented: 3, frames: 10, failures: 0, resent: 0 });
+  rx.reset_stats();
+  assert_eq!(rx.stats(), FragmentRxStats::default());
+  assert!(rx.device_stats().is_empty());
+}
+
+#[test]
+fn stats_abandoned() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+
+  let stale = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0xAA; 40]));
+  let fresh = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0x55; 12]));
+
+  assert_eq!(defragment(&mut rx, &stale[..3]), Ok(None));
+  assert_eq!(defragment(&mut rx, &fresh), Ok(Some(misc(&[0x55; 12]))));
+  assert_eq!(rx.stats(), FragmentRxStats { started: 2, completed: 1, abandoned: 1, ..Default::default() });
+}
+
+#[test]
+fn fixed_stats() {
+  let mut rx = FixedFragmentReassemblerRx::<2, 64>::new(1000);
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  let a = fragment_from(&mut tx, 1, misc(&[0x11; 20]));
+  let b = fragment_from(&mut tx, 2, misc(&[0x22; 20]));
+  let c = fragment_from(&mut tx, 3, misc(&[0x33; 20]));
+
+  assert_eq!(defragment_fixed(&mut rx, 0, &a[..1]), Ok(None));
+  assert_eq!(defragment_fixed(&mut rx, 1, &b[..1]), Ok(None));
+  assert_eq!(defragment_fixed(&mut rx, 2, &c[..1]), Ok(None));
+  assert_eq!(defragment_fixed(&mut rx, 3, [&b[1], &b[1], &b[2]]), Ok(Some(misc(&[0x22; 20]))));
+  assert_eq!(rx.stats(), FragmentRxStats { started: 3, completed: 1, abandoned: 1, in_flight: 1, duplicates: 1, ..Default::default() });
+
+  rx.prune(1003);
+  assert_eq!(rx.stats(), FragmentRxStats { started: 3, completed: 1, abandoned: 1, aged_off: 1, duplicates: 1, ..Default::default() });
+}
+
+fn fragment_id(frames: &[(MessageId, Vec<u8>)]) -> u8 {
+  frames[0].0.api_class & 0b1111
+}
+
+#[test]
+fn fragment_ids_per_device() {
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  // IDs cycle through all 4 bits, then wrap
+  let ids: Vec<_> = (0..17).map(|_| fragment_id(&fragment_from(&mut tx, 1, misc(&[0; 20])))).collect();
+  assert_eq!(ids, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1]);
+
+  // Other devices have IDs of their own
+  assert_eq!(fragment_id(&fragment_from(&mut tx, 2, misc(&[0; 20]))), 1);
+  let set_name = GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::SetName {
+    serial: 0, name: AsymmetricCow(Cow::Borrowed("A much longer device name"))
+  }));
+  assert_eq!(fragment_id(&fragment_from(&mut tx, 1, set_name)), 1);
+  assert_eq!(fragment_id(&fragment_from(&mut tx, 1, misc(&[0; 20]))), 2);
+}
+
+#[test]
+fn fragment_ids_in_flight() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  let id = |frames: &FragmentFrames| fragment_id(&frames.clone().map(|(id, data)| (id, data.to_vec())).collect::<Vec<_>>());
+
+  // Overlapping transfers to the same device never share an ID
+  let a = tx.fragment(1, misc(&[0x11; 20])).unwrap();
+  let b = tx.fragment(1, misc(&[0x22; 20])).unwrap();
+  assert_eq!((id(&a), id(&b)), (1, 2));
+
+  // Sending frames in between doesn't free the ID, only releasing the transfer does
+  let c = fragment_from(&mut tx, 1, misc(&[0x33; 20]));
+  assert_eq!(fragment_id(&c), 3);
+
+  let rest: Vec<_> = (0..13).map(|_| tx.fragment(1, misc(&[0; 20])).unwrap()).collect();
+  assert_eq!(rest.iter().map(id).collect::<Vec<_>>(), [4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0]);
+  assert_eq!(id(&tx.fragment(1, misc(&[0; 20])).unwrap()), 3);
+
+  // Every ID is now in flight
+  assert_eq!(tx.fragment(1, misc(&[0; 20])).err(), Some(FragmentError::IdsExhausted));
+  assert_eq!(tx.maybe_fragment(1, misc(&[0; 20]), &mut |_, _| {}), Err(FragmentError::IdsExhausted));
+  assert_eq!(fragment_id(&fragment_from(&mut tx, 2, misc(&[0; 20]))), 1);
+  // Messages that fit in one frame need no ID
+  assert_eq!(fragment_from(&mut tx, 1, misc(&[0; 2])).len(), 1);
+
+  tx.release(&b);
+  let d = tx.fragment(1, misc(&[0x44; 20])).unwrap();
+  assert_eq!(id(&d), 2);
+
+  // Releasing a transfer again doesn't free the ID it has passed on to another
+  tx.release(&b);
+  assert_eq!(tx.fragment(1, misc(&[0; 20])).err(), Some(FragmentError::IdsExhausted));
+
+  // Both in-flight transfers still reassemble correctly when interleaved
+  let frames: Vec<_> = a.zip(d).flat_map(|(x, y)| [(x.0, x.1.to_vec()), (y.0, y.1.to_vec())]).collect();
+  let mut received = vec![];
+  for frame in &frames {
+    if let Some(msg) = defragment(&mut rx, [frame]).unwrap() {
+      received.push(msg);
+    }
+  }
+  assert_eq!(received, [misc(&[0x11; 20]), misc(&[0x44; 20])]);
+}
+
+#[test]
+fn fragment_frames_interleaved() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+
+  // Queue several transfers, including two to the same device, and send them a frame at a time
+  let mut queued: Vec<_> = [(1, misc(&[0x11; 30])), (2, misc(&[0x22; 300])), (1, misc(&[0x33; 2])), (1, misc(&[0x44; 30]))]
+    .into_iter()
+    .map(|(device_id, msg)| tx.fragment(device_id, msg).unwrap())
+    .collect();
+
+  let mut received = vec![];
+  while queued.iter().any(|frames| frames.len() > 0) {
+    for frames in queued.iter_mut() {
+      let before = frames.len();
+      if let Some(frame) = frames.next() {
+        assert_eq!(frames.len(), before - 1);
+        if let Some(msg) = defragment(&mut rx, [&(frame.0, frame.1.to_vec())]).unwrap() {
+          received.push(msg);
+        }
+      }
+    }
+  }
+
+  assert_eq!(received, [misc(&[0x33; 2]), misc(&[0x11; 30]), misc(&[0x44; 30]), misc(&[0x22; 300])]);
+}
+
+#[test]
+fn fragment_accessors() {
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+  let frames = fragment(&mut tx, misc(&[0x55; 12]));
+
+  let decode = |(id, data): &(MessageId, Vec<u8>)| match MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap() {
+    MaybeFragment::Fragment(frag) => frag.to_static(),
+    MaybeFragment::Message(_) => panic!("expected a fragment"),
+  };
+
+  let start = decode(&frames[0]);
+  assert_eq!((start.fragment_id(), start.extended(), start.index()), (1, false, 0));
+  assert!(matches!(start.body(), FragmentBody::Start { total_len: 12, crc: None, .. }));
+  assert_eq!(start.payload(), [0x55; 5]);
+
+  let last = decode(&frames[1]);
+  assert_eq!((last.fragment_id(), last.extended(), last.index(), last.body()), (1, false, 1, &FragmentBody::Fragment));
+  assert_eq!(last.payload(), [0x55; 7]);
+
+  // Building the same fragment by hand gives the same frame
+  let mut frag = Fragment::new(1, false, 1, FragmentBody::Fragment, &[0x55; 7]);
+  let mut id = GrappleMessageId::new(1);
+  id.device_type = frames[1].0.device_type;
+  frag.update(&mut id);
+  let mut writer = VecBitWriter::new();
+  frag.write(&mut writer, id.clone()).unwrap();
+  assert_eq!((MessageId::from(id), writer.slice().to_vec()), frames[1]);
+}
+
+#[test]
+fn standard_fields_out_of_range() {
+  let write = |fragment_id, extended, index| {
+    let mut frag = Fragment::new(fragment_id, extended, index, FragmentBody::Fragment, &[0x55; 4]);
+    let mut id = GrappleMessageId::new(1);
+    frag.update(&mut id);
+    frag.write(&mut VecBitWriter::new(), id)
+  };
+
+  // A 17 fragment standard transfer has nowhere to put its last index, so it fails rather than wrap
+  for index in 1..16 {
+    assert_eq!(write(1, false, index), Ok(()), "index {}", index);
+  }
+  assert!(matches!(write(1, false, 16), Err(binmarshal::MarshalError::IllegalValue { .. })));
+  assert_eq!(write(1, true, 16), Ok(()));
+
+  // Fragment IDs only have 4 bits in either format
+  assert!(matches!(write(16, false, 1), Err(binmarshal::MarshalError::IllegalValue { .. })));
+  assert!(matches!(write(16, true, 1), Err(binmarshal::MarshalError::IllegalValue { .. })));
+
+  // The transmitter switches to the extended format instead
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+  let frames = fragment(&mut tx, misc(&[0x55; 5 + 8 * 16]));
+  assert!(frames.len() > 16);
+  assert!(frames.iter().all(|(id, _)| id.api_class & 0b100000 != 0));
+}
+
+type Frames = Vec<(MessageId, Vec<u8>)>;
+
+fn nack_indices(frames: &[(MessageId, Vec<u8>)]) -> Vec<u16> {
+  frames.iter().flat_map(|(id, data)| match MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap() {
+    MaybeFragment::Fragment(frag) => match frag.body() {
+      FragmentBody::Nack { missing } => missing.clone(),
+      _ => panic!("expected a NACK"),
+    },
+    MaybeFragment::Message(_) => panic!("expected a fragment"),
+  }).collect()
+}
+
+// Has the receiver NACK at `now`, and the transmitter answer it
+fn nack_and_resend(rx: &mut FragmentReassemblerRx, tx: &mut FragmentReassemblerTx, now: i64) -> (Frames, Frames) {
+  let mut nacks = vec![];
+  rx.poll_nacks(now, &mut |id, data| nacks.push((id, data.to_vec())));
+
+  let mut resent = vec![];
+  for (id, data) in &nacks {
+    if let MaybeFragment::Fragment(nack) = MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap() {
+      tx.resend(id, &nack, &mut |id, data| resent.push((id, data.to_vec())));
+    }
+  }
+  (nacks, resent)
+}
+
+#[test]
+fn nack_resends_lost_fragments() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+  tx.set_retransmit(4);
+
+  // Lose one fragment from the middle, and the last
+  let frames = fragment(&mut tx, misc(&[0x55; 40]));
+  assert_eq!(frames.len(), 6);
+  assert_eq!(defragment_at(&mut rx, 0, [&frames[0], &frames[1], &frames[3], &frames[4]]), Ok(None));
+
+  // Not quiet for long enough yet
+  assert_eq!(nack_and_resend(&mut rx, &mut tx, 50).0, []);
+
+  let (nacks, resent) = nack_and_resend(&mut rx, &mut tx, 100);
+  assert_eq!(nacks.len(), 1);
+  // Flagged as an extended fragment with api_index 1, and addressed like the transfer
+  assert_eq!(nacks[0].0, MessageId { api_class: 0b110000 | fragment_id(&frames), api_index: 1, ..frames[0].0 });
+  assert_eq!(nacks[0].1, [0, 2, 0, 5]);
+  assert_eq!(resent, [frames[2].clone(), frames[5].clone()]);
+
+  assert_eq!(defragment_at(&mut rx, 150, &resent), Ok(Some(misc(&[0x55; 40]))));
+  assert_eq!(rx.stats().nacks, 1);
+  assert_eq!(tx.stats().resent, 2);
+}
+
+#[test]
+fn nack_lost_start_and_extended() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+  tx.set_retransmit(4);
+
+  let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+  let frames = fragment(&mut tx, misc(&payload));
+  let lost = [0, 7, 8, 20, frames.len() - 1];
+  let kept: Vec<_> = frames.iter().enumerate().filter(|(i, _)| !lost.contains(i)).map(|(_, f)| f).collect();
+  assert_eq!(defragment_at(&mut rx, 0, kept), Ok(None));
+
+  // Without the start we can't tell how long the message is, so only the gaps are asked for
+  let (nacks, resent) = nack_and_resend(&mut rx, &mut tx, 100);
+  assert_eq!(nack_indices(&nacks), [0, 7, 8, 20]);
+  assert_eq!(defragment_at(&mut rx, 100, &resent), Ok(None));
+
+  // A NACK isn't repeated until the transfer has been quiet again
+  assert_eq!(nack_and_resend(&mut rx, &mut tx, 150).0, []);
+
+  let (nacks, resent) = nack_and_resend(&mut rx, &mut tx, 200);
+  assert_eq!(nack_indices(&nacks), [frames.len() as u16 - 1]);
+  assert_eq!(defragment_at(&mut rx, 200, &resent), Ok(Some(misc(&payload))));
+}
+
+#[test]
+fn nack_sizes_fragments_from_start() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+  tx.set_retransmit(4);
+
+  // The only other fragment we hear is the short last one, which mustn't be taken as the usual length
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+  assert_eq!(frames.iter().map(|f| f.1.len()).collect::<Vec<_>>(), [8, 8, 7]);
+  assert_eq!(defragment_at(&mut rx, 0, [&frames[0], &frames[2]]), Ok(None));
+  let (nacks, resent) = nack_and_resend(&mut rx, &mut tx, 100);
+  assert_eq!(nack_indices(&nacks), [1]);
+  assert_eq!(defragment_at(&mut rx, 100, &resent), Ok(Some(misc(&[0x55; 20]))));
+
+  // Extended fragments carry less than their start does
+  let payload: Vec<u8> = (0..300).map(|i| i as u8).collect();
+  let frames = fragment(&mut tx, misc(&payload));
+  let n = frames.len() as u16;
+  assert_eq!(defragment_at(&mut rx, 200, &frames[..frames.len() - 2]), Ok(None));
+  assert_eq!(nack_indices(&nack_and_resend(&mut rx, &mut tx, 300).0), [n - 2, n - 1]);
+}
+
+#[test]
+fn nack_for_dropped_transfer_is_ignored() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+  tx.set_retransmit(1);
+
+  let first = fragment_from(&mut tx, 1, misc(&[0x11; 20]));
+  let _ = fragment_from(&mut tx, 2, misc(&[0x22; 20]));
+
+  assert_eq!(defragment_at(&mut rx, 0, &first[..1]), Ok(None));
+  let (nacks, resent) = nack_and_resend(&mut rx, &mut tx, 100);
+  assert_eq!(nack_indices(&nacks), [1, 2]);
+  assert_eq!(resent, []);
+}
+
+#[test]
+fn nack_read_is_inline() {
+  let id = GrappleMessageId { device_type: 30, fragment_flag: true, ack_flag: true, api_class: 3, api_index: 1, device_id: 1 };
+
+  // Reading a NACK must not allocate, since the fixed reassembler decodes through the same path
+  match MaybeFragment::read(&mut BitView::new(&[0, 1, 0, 2, 0, 3, 0, 4]), id.clone()).unwrap() {
+    MaybeFragment::Fragment(frag) => match frag.body() {
+      FragmentBody::Nack { missing } => {
+        assert_eq!(&missing[..], [1, 2, 3, 4]);
+        assert!(!missing.spilled());
+      },
+      _ => panic!("expected a NACK"),
+    },
+    MaybeFragment::Message(_) => panic!("expected a fragment"),
+  }
+
+  // More indices than fit in a classic frame, or half an index
+  assert!(MaybeFragment::read(&mut BitView::new(&[0; 10]), id.clone()).is_err());
+  assert!(MaybeFragment::read(&mut BitView::new(&[0; 3]), id.clone()).is_err());
+
+  let nack = Fragment::new(3, true, 0, FragmentBody::Nack { missing: (0..5).collect() }, &[]);
+  assert!(nack.write(&mut VecBitWriter::new(), id.clone()).is_err());
+
+  let mut rx = FixedFragmentReassemblerRx::<2, 64>::new(1000);
+  let nack = MaybeFragment::read(&mut BitView::new(&[0, 1]), id.clone()).unwrap();
+  assert_eq!(rx.defragment(0, &id.into(), nack).map(|x| x.is_none()), Ok(true));
+}
+
+#[test]
+fn retained_transfers_hold_fragment_ids() {
+  let (_, mut tx) = FragmentReassembler::new(1000, 8).split();
+  tx.set_retransmit(32);
+
+  // Every ID is held, so the 17th transfer takes the ID of the oldest
+  let ids: Vec<_> = (0..17).map(|_| fragment_id(&fragment_from(&mut tx, 1, misc(&[0; 20])))).collect();
+  assert_eq!(ids, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn nack_over_transport() {
+  use grapple_frc_msgs::{can::CanFrameFormat, transport::{loopback::LoopbackBus, CanTransport, GrappleTransport}};
+
+  let bus = LoopbackBus::new(CanFrameFormat::Classic);
+  let mut sender = GrappleTransport::new(bus.node(), 1_000_000);
+  let mut receiver = GrappleTransport::new(bus.node(), 1_000_000);
+  let mut lossy = bus.node();
+  sender.tx_mut().set_retransmit(4);
+  receiver.rx_mut().set_nack(Some(0));
+
+  // Send the sender's frames through a lossy node, dropping one
+  let frames: Vec<_> = sender.tx_mut().fragment(1, misc(&[0x55; 40])).unwrap().collect();
+  for (i, (id, data)) in frames.iter().enumerate() {
+    if i != 2 {
+      lossy.send(*id, data).unwrap();
+    }
+  }
+
+  assert_eq!(receiver.recv(), Ok(None));
+  assert_eq!(sender.recv(), Ok(None));
+  assert_eq!(receiver.recv().unwrap().map(|(_, msg)| msg), Some(misc(&[0x55; 40])));
+  assert_eq!(sender.tx().stats().resent, 1);
+}
+
+#[test]
+fn defragment_owned() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+
+  let mut reassembled = None;
+  for (t, (id, data)) in frames.iter().enumerate() {
+    let frag = MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap();
+    reassembled = rx.defragment_owned(t as i64 * 10, id, frag).unwrap();
+  }
+
+  // The message outlives the frames, and can be handed to another thread
+  drop(frames);
+  let (send, recv) = std::sync::mpsc::channel::<ReassembledMessage>();
+  std::thread::spawn(move || send.send(reassembled.unwrap()).unwrap()).join().unwrap();
+
+  let msg = recv.recv().unwrap();
+  assert_eq!((msg.id.device_id, msg.timestamp, msg.message), (1, 20, misc(&[0x55; 20])));
+}
+
+#[test]
+fn channels_reassemble_separately() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+
+  // The same device ID on two buses, and fresh transmitters, so the transfers share a key
+  let a = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0xAA; 20]));
+  let b = fragment(&mut FragmentReassembler::new(1000, 8).split().1, misc(&[0xBB; 20]));
+
+  let mut received = vec![];
+  for (fa, fb) in a.iter().zip(b.iter()) {
+    for (channel, (id, data)) in [(0, fa), (1, fb)] {
+      let frag = MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap();
+      if let Some(msg) = rx.defragment_owned_on_channel(channel, 0, id, frag).unwrap() {
+        received.push((msg.channel, msg.message));
+      }
+    }
+  }
+  assert_eq!(received, [(0, misc(&[0xAA; 20])), (1, misc(&[0xBB; 20]))]);
+
+  let channels: Vec<_> = rx.device_stats().keys().map(|k| k.channel).collect();
+  assert_eq!(channels, [0, 1]);
+}
+
+#[test]
+fn nacks_per_channel() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+
+  let frames = fragment(&mut tx, misc(&[0x55; 20]));
+  let mut storage = vec![];
+  let frag = MaybeFragment::read(&mut BitView::new(&frames[0].1), frames[0].0.into()).unwrap();
+  assert!(rx.defragment_on_channel(3, 0, &frames[0].0, frag, &mut storage).unwrap().is_none());
+
+  let mut nacks = vec![];
+  rx.poll_nacks(100, &mut |id, data| nacks.push((id, data.to_vec())));
+  assert_eq!(nacks, []);
+  rx.poll_nacks_on_channel(3, 100, &mut |id, data| nacks.push((id, data.to_vec())));
+  assert_eq!(nack_indices(&nacks), [1, 2]);
+}
+
+#[test]
+fn resend_per_channel() {
+  let (mut rx, mut tx) = FragmentReassembler::new(1000, 8).split();
+  rx.set_nack(Some(100));
+  tx.set_retransmit(4);
+
+  // The same device on two buses gets its own fragment IDs on each
+  let mut on_channel = |channel, payload: &[u8]| {
+    let mut frames = vec![];
+    tx.maybe_fragment_on_channel(channel, 1, misc(payload), &mut |id, data| frames.push((id, data.to_vec()))).unwrap();
+    frames
+  };
+  let a = on_channel(0, &[0xAA; 20]);
+  let b = on_channel(1, &[0xBB; 20]);
+  assert_eq!((fragment_id(&a), fragment_id(&b)), (1, 1));
+
+  // Lose the middle of channel 1's transfer
+  assert_eq!(b.len(), 3);
+  let mut storage = vec![];
+  for (id, data) in [&b[0], &b[2]] {
+    let frag = MaybeFragment::read(&mut BitView::new(data), (*id).into()).unwrap();
+    assert!(rx.defragment_on_channel(1, 0, id, frag, &mut storage).unwrap().is_none());
+  }
+
+  let mut nacks = vec![];
+  rx.poll_nacks_on_channel(1, 100, &mut |id, data| nacks.push((id, data.to_vec())));
+  let MaybeFragment::Fragment(nack) = MaybeFragment::read(&mut BitView::new(&nacks[0].1), nacks[0].0.into()).unwrap() else { panic!("expected a fragment") };
+
+  // Only the transfer on the channel the NACK was heard on is resent
+  let mut resent = vec![];
+  tx.resend_on_channel(1, &nacks[0].0, &nack, &mut |id, data| resent.push((id, data.to_vec())));
+  assert_eq!(nack_indices(&nacks), [1]);
+  assert_eq!(resent, [b[1].clone()]);
+
+  let frag = MaybeFragment::read(&mut BitView::new(&resent[0].1), resent[0].0.into()).unwrap();
+  let (_, msg) = rx.defragment_on_channel(1, 100, &resent[0].0, frag, &mut storage).unwrap().unwrap();
+  assert_eq!(msg.to_static(), misc(&[0xBB; 20]));
+}
+
+// Feeds a hand-built fragment from a Misc device straight to the receiver
+fn defragment_raw(rx: &mut FragmentReassemblerRx, now: i64, device_id: u8, frag: Fragment<'_>) -> Result<bool, DefragmentError> {
+  let mut id = GrappleMessageId::new(device_id);
+  id.device_type = 30;
+  let mut frag = frag;
+  frag.update(&mut id);
+  let mut storage = vec![];
+  rx.defragment(now, &id.into(), MaybeFragment::Fragment(frag), &mut storage).map(|x| x.is_some())
+}
+
+fn start(extended: bool, total_len: u16, payload: &[u8]) -> Fragment<'_> {
+  Fragment::new(1, extended, 0, FragmentBody::Start { api_class: 0, api_index: 0, total_len, crc: None }, payload)
+}
+
+#[test]
+fn empty_fragments_rejected() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+  let empty = |index| Fragment::new(1, true, index, FragmentBody::Fragment, &[]);
+
+  for index in 1..100 {
+    assert_eq!(defragment_raw(&mut rx, 0, 1, empty(index)), Err(DefragmentError::Marshal(binmarshal::MarshalError::CoercionError)));
+  }
+  assert_eq!(rx.stats(), FragmentRxStats::default());
+
+  // An empty start is fine in the extended format
+  assert_eq!(defragment_raw(&mut rx, 0, 1, start(true, 10, &[])), Ok(false));
+  assert_eq!(rx.stats().in_flight, 1);
+}
+
+#[test]
+fn fragment_count_limit() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+  let byte = |index| Fragment::new(1, true, index, FragmentBody::Fragment, &[0x55]);
+
+  // Once the start says how long the message is, it can't be split into more than a few fragments
+  assert_eq!(defragment_raw(&mut rx, 0, 1, start(true, 20, &[0x55])), Ok(false));
+  for index in 10..15 {
+    assert_eq!(defragment_raw(&mut rx, 0, 1, byte(index)), Ok(false));
+  }
+  assert_eq!(defragment_raw(&mut rx, 0, 1, byte(15)), Err(DefragmentError::Marshal(binmarshal::MarshalError::BufferTooSmall)));
+  assert_eq!(rx.stats(), FragmentRxStats { started: 1, decode_failures: 1, ..Default::default() });
+
+  // Before the start, any fragments beyond what the longest message could need are rejected
+  let mut index = 1;
+  let err = loop {
+    if let Err(e) = defragment_raw(&mut rx, 0, 2, byte(index)) {
+      break e;
+    }
+    index += 1;
+  };
+  assert_eq!(err, DefragmentError::Marshal(binmarshal::MarshalError::BufferTooSmall));
+  assert_eq!(index, 16386);
+  assert_eq!(rx.stats().in_flight, 0);
+}
+
+#[test]
+fn transfer_limit() {
+  let (mut rx, _) = FragmentReassembler::new(1000, 8).split();
+  rx.set_max_transfers(4);
+
+  for device_id in 1..=5 {
+    assert_eq!(defragment_raw(&mut rx, device_id as i64, device_id, start(false, 20, &[0x55; 5])), Ok(false));
+  }
+  assert_eq!(rx.stats(), FragmentRxStats { started: 5, abandoned: 1, in_flight: 4, ..Default::default() });
+
+  // The quietest transfer made room for the newest
+  let in_flight: Vec<_> = rx.device_stats().iter().filter(|(_, s)| s.in_flight > 0).map(|(k, _)| k.device_id).collect();
+  assert_eq!(in_flight, [2, 3, 4, 5]);
+
+  // Hearing from a transfer again keeps it from being evicted
+  assert_eq!(defragment_raw(&mut rx, 10, 2, Fragment::new(1, false, 1, FragmentBody::Fragment, &[0x55; 8])), Ok(false));
+  assert_eq!(defragment_raw(&mut rx, 11, 6, start(false, 20, &[0x55; 5])), Ok(false));
+  let in_flight: Vec<_> = rx.device_stats().iter().filter(|(_, s)| s.in_flight > 0).map(|(k, _)| k.device_id).collect();
+  assert_eq!(in_flight, [2, 4, 5, 6]);
+
+  rx.set_max_transfers(2);
+  assert_eq!(rx.stats().in_flight, 2);
+}
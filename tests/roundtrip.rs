@@ -0,0 +1,534 @@
+#![cfg(all(feature = "ni", feature = "grapple_lasercan", feature = "grapple_mitocandria", feature = "grapple_flexican", feature = "grapple_jms", feature = "firmware_update_v1"))]
+
+// Property-based round-trip tests. Every case is generated from its own seed, so a failure can be
+// reproduced by re-running that seed.
+
+use std::borrow::Cow;
+
+use binmarshal::{AsymmetricCow, BitView, BitWriter, Demarshal, LengthTaggedPayloadOwned, Marshal, PayloadOwned, VecBitWriter};
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{
+  bridge::BridgedCANMessage,
+  can::CanFrameFormat,
+  grapple::{
+    device_info::{GrappleDeviceInfo, GrappleModelId},
+    encapsulation::{BridgeMessages, EncapsulatedMesssage},
+    errors::{GrappleError, GrappleResult, ParameterRange},
+    firmware::{FlashParameters, GrappleFirmwareMessage, UpdatePartV2Payload},
+    flexican::FlexiCANMessage,
+    fragments::{FixedFragmentReassemblerRx, FragmentReassembler, MAX_STANDARD_FRAGMENTS},
+    jms::{Colour, JMSCardStatus, JMSCardUpdate, JMSElectronicsStatus, JMSElectronicsUpdate, JMSMessage, JMSRole, Pattern},
+    lasercan::{LaserCanMeasurement, LaserCanMessage, LaserCanRangingMode, LaserCanRoi, LaserCanRoiU4, LaserCanTimingBudget},
+    misc::MiscMessage,
+    mitocandria::{
+      MitocandriaAdjustableChannelCalibrationRequest, MitocandriaAdjustableChannelRequest, MitocandriaChannelRequest,
+      MitocandriaChannelStatus, MitocandriaMessage, MitocandriaStatusFrame, MitocandriaSwitchableChannelRequest
+    },
+    GrappleBroadcastMessage, GrappleDeviceMessage, GrappleMessageId, MaybeFragment, Request,
+  },
+  ni::{NiDeviceMessage, NiRioHearbeat1, NiRioHeartbeat, NiRobotControllerMessage},
+  ManufacturerMessage, Message, MessageId,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+const CASES: u64 = 512;
+
+// Strings and payloads are kept short enough that every message fits in the standard fragment
+// format on a classic CAN bus. `large_messages` covers the extended format.
+const MAX_STRING_LEN: usize = 24;
+const MAX_PAYLOAD_LEN: usize = 64;
+
+trait Generate: Sized {
+  fn generate(rng: &mut StdRng) -> Self;
+}
+
+impl Generate for u8 {
+  fn generate(rng: &mut StdRng) -> Self { rng.gen() }
+}
+
+impl Generate for () {
+  fn generate(_rng: &mut StdRng) -> Self {}
+}
+
+impl<T: Generate, const N: usize> Generate for [T; N] {
+  fn generate(rng: &mut StdRng) -> Self {
+    core::array::from_fn(|_| T::generate(rng))
+  }
+}
+
+impl<R: Generate, A: Generate> Generate for Request<R, A> {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() { Request::Ack(A::generate(rng)) } else { Request::Request(R::generate(rng)) }
+  }
+}
+
+impl<T: Generate> Generate for GrappleResult<'static, T> {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() { Ok(T::generate(rng)) } else { Err(GrappleError::generate(rng)) }
+  }
+}
+
+// Strings are null-terminated on the wire, so they can't contain a null.
+fn string(rng: &mut StdRng) -> AsymmetricCow<'static, str> {
+  const EXTRA: [char; 4] = ['é', 'λ', '→', '🤖'];
+
+  let mut s = String::new();
+  let target = rng.gen_range(0..=MAX_STRING_LEN);
+  loop {
+    let c = if rng.gen_ratio(1, 8) { EXTRA[rng.gen_range(0..EXTRA.len())] } else { rng.gen_range(' '..='~') };
+    if s.len() + c.len_utf8() > target {
+      break;
+    }
+    s.push(c);
+  }
+  AsymmetricCow(Cow::Owned(s))
+}
+
+fn bytes(rng: &mut StdRng) -> Vec<u8> {
+  let len = rng.gen_range(0..=MAX_PAYLOAD_LEN);
+  (0..len).map(|_| rng.gen()).collect()
+}
+
+fn message_id(rng: &mut StdRng) -> MessageId {
+  MessageId::from(rng.gen_range(0..(1u32 << 29)))
+}
+
+impl Generate for GrappleError<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..10) {
+      0 => GrappleError::ParameterOutOfBounds(string(rng)),
+      1 => GrappleError::FailedAssertion(string(rng)),
+      2 => GrappleError::TimedOut(string(rng)),
+      3 => GrappleError::ParameterOutOfRange(ParameterRange { index: rng.gen(), min: rng.gen(), max: rng.gen() }),
+      4 => GrappleError::Unsupported,
+      5 => GrappleError::Busy,
+      6 => GrappleError::InvalidState,
+      7 => GrappleError::NotFound,
+      8 => GrappleError::FlashError(rng.gen()),
+      _ => GrappleError::Generic(string(rng)),
+    }
+  }
+}
+
+/* LASERCAN */
+
+impl Generate for LaserCanRoi {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self {
+      x: LaserCanRoiU4(rng.gen_range(1..=16)),
+      y: LaserCanRoiU4(rng.gen_range(1..=16)),
+      w: LaserCanRoiU4(rng.gen_range(1..=16)),
+      h: LaserCanRoiU4(rng.gen_range(1..=16)),
+    }
+  }
+}
+
+impl Generate for LaserCanTimingBudget {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..4) {
+      0 => LaserCanTimingBudget::TB20ms,
+      1 => LaserCanTimingBudget::TB33ms,
+      2 => LaserCanTimingBudget::TB50ms,
+      _ => LaserCanTimingBudget::TB100ms,
+    }
+  }
+}
+
+impl Generate for LaserCanRangingMode {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() { LaserCanRangingMode::Long } else { LaserCanRangingMode::Short }
+  }
+}
+
+impl Generate for LaserCanMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..5) {
+      0 => LaserCanMessage::Measurement(LaserCanMeasurement {
+        status: rng.gen(),
+        distance_mm: rng.gen(),
+        ambient: rng.gen(),
+        mode: LaserCanRangingMode::generate(rng),
+        budget: LaserCanTimingBudget::generate(rng),
+        roi: LaserCanRoi::generate(rng),
+      }),
+      1 => LaserCanMessage::SetRange(Request::generate(rng)),
+      2 => LaserCanMessage::SetRoi(Request::generate(rng)),
+      3 => LaserCanMessage::SetTimingBudget(Request::generate(rng)),
+      _ => LaserCanMessage::SetLedThreshold(if rng.gen() { Request::Request(rng.gen()) } else { Request::Ack(GrappleResult::generate(rng)) }),
+    }
+  }
+}
+
+/* MITOCANDRIA */
+
+impl Generate for MitocandriaChannelStatus {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..3) {
+      0 => MitocandriaChannelStatus::Switchable { enabled: rng.gen(), current: rng.gen() },
+      1 => MitocandriaChannelStatus::NonSwitchable { current: rng.gen() },
+      _ => MitocandriaChannelStatus::Adjustable { enabled: rng.gen(), voltage: rng.gen(), voltage_setpoint: rng.gen(), current: rng.gen() },
+    }
+  }
+}
+
+impl Generate for MitocandriaSwitchableChannelRequest {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self { channel: rng.gen(), enabled: rng.gen() }
+  }
+}
+
+impl Generate for MitocandriaAdjustableChannelRequest {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self { channel: rng.gen(), voltage: rng.gen() }
+  }
+}
+
+impl Generate for MitocandriaAdjustableChannelCalibrationRequest {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self { offset_mv: rng.gen() }
+  }
+}
+
+impl Generate for MitocandriaMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..5) {
+      0 => MitocandriaMessage::StatusFrame(MitocandriaStatusFrame { channels: Generate::generate(rng) }),
+      1 => MitocandriaMessage::ChannelRequest(MitocandriaChannelRequest::SetSwitchableChannel(Request::generate(rng))),
+      2 => MitocandriaMessage::ChannelRequest(MitocandriaChannelRequest::SetAdjustableChannel(Request::generate(rng))),
+      3 => MitocandriaMessage::ChannelRequest(MitocandriaChannelRequest::CalibrateAdjChannel(Request::generate(rng))),
+      _ => MitocandriaMessage::ChannelRequest(MitocandriaChannelRequest::StartAutoCalibrate(Request::generate(rng))),
+    }
+  }
+}
+
+/* JMS */
+
+impl Generate for JMSRole {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..5) {
+      0 => JMSRole::ScoringTable,
+      1 => JMSRole::Red(rng.gen()),
+      2 => JMSRole::Blue(rng.gen()),
+      3 => JMSRole::TimerRed,
+      _ => JMSRole::TimerBlue,
+    }
+  }
+}
+
+impl Generate for JMSCardStatus {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() { JMSCardStatus::IO(rng.gen()) } else { JMSCardStatus::Lighting }
+  }
+}
+
+impl Generate for Colour {
+  fn generate(rng: &mut StdRng) -> Self {
+    Colour::new(rng.gen(), rng.gen(), rng.gen())
+  }
+}
+
+impl Generate for Pattern {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..5) {
+      0 => Pattern::Blank,
+      1 => Pattern::Solid(Colour::generate(rng)),
+      2 => Pattern::DiagonalStripes(Colour::generate(rng), Colour::generate(rng)),
+      3 => Pattern::FillLeft(Colour::generate(rng), Colour::generate(rng), rng.gen()),
+      _ => Pattern::FillRight(Colour::generate(rng), Colour::generate(rng), rng.gen()),
+    }
+  }
+}
+
+impl Generate for JMSCardUpdate<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() {
+      JMSCardUpdate::IO()
+    } else {
+      JMSCardUpdate::Lighting {
+        text_back: string(rng),
+        text_back_colour: Colour::generate(rng),
+        back_background: Pattern::generate(rng),
+        text: string(rng),
+        text_colour: Colour::generate(rng),
+        bottom_bar: Pattern::generate(rng),
+        top_bar: Pattern::generate(rng),
+        background: Pattern::generate(rng),
+      }
+    }
+  }
+}
+
+impl Generate for JMSMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..4) {
+      0 => JMSMessage::Status(JMSElectronicsStatus { role: JMSRole::generate(rng), cards: Generate::generate(rng) }),
+      1 => JMSMessage::SetRole(JMSRole::generate(rng)),
+      2 => JMSMessage::Update(JMSElectronicsUpdate { card: rng.gen(), update: JMSCardUpdate::generate(rng) }),
+      _ => JMSMessage::Blink,
+    }
+  }
+}
+
+impl Generate for MiscMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    if rng.gen() {
+      MiscMessage::MiscMessage(AsymmetricCow(Cow::Owned(PayloadOwned::new(bytes(rng)))))
+    } else {
+      MiscMessage::JMS(JMSMessage::generate(rng))
+    }
+  }
+}
+
+/* BRIDGE */
+
+impl Generate for AsymmetricCow<'static, str> {
+  fn generate(rng: &mut StdRng) -> Self { string(rng) }
+}
+
+impl Generate for BridgeMessages<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..4) {
+      0 => BridgeMessages::GetChannelName(Request::generate(rng)),
+      1 => BridgeMessages::StartBridge(Request::generate(rng)),
+      2 => BridgeMessages::StopBridge(Request::generate(rng)),
+      _ => BridgeMessages::BridgeMessage(EncapsulatedMesssage {
+        channel: rng.gen(),
+        timestamp: rng.gen(),
+        id: message_id(rng),
+        data: AsymmetricCow(Cow::Owned(LengthTaggedPayloadOwned::new(bytes(rng)))),
+      }),
+    }
+  }
+}
+
+impl Generate for FlexiCANMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    FlexiCANMessage::Bridge(BridgeMessages::generate(rng))
+  }
+}
+
+/* FIRMWARE */
+
+impl Generate for UpdatePartV2Payload<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self { offset: rng.gen(), payload: AsymmetricCow(Cow::Owned(PayloadOwned::new(bytes(rng)))) }
+  }
+}
+
+impl Generate for FlashParameters {
+  fn generate(rng: &mut StdRng) -> Self {
+    Self { flash_compat_version: rng.gen(), align: rng.gen(), payload_len: rng.gen() }
+  }
+}
+
+impl Generate for GrappleFirmwareMessage<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..6) {
+      0 => GrappleFirmwareMessage::StartFieldUpgrade { serial: rng.gen() },
+      1 => GrappleFirmwareMessage::UpdatePart(AsymmetricCow(Cow::Owned(PayloadOwned::new(bytes(rng))))),
+      2 => GrappleFirmwareMessage::UpdatePartAck,
+      3 => GrappleFirmwareMessage::UpdateDone,
+      4 => GrappleFirmwareMessage::UpdatePartV2(Request::generate(rng)),
+      _ => GrappleFirmwareMessage::GetFlashParameters(Request::generate(rng)),
+    }
+  }
+}
+
+/* DEVICE INFO */
+
+impl Generate for GrappleModelId {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..4) {
+      0 => GrappleModelId::LaserCan,
+      1 => GrappleModelId::SpiderLan,
+      2 => GrappleModelId::FlexiCAN,
+      _ => GrappleModelId::MitoCANdria,
+    }
+  }
+}
+
+impl Generate for GrappleDeviceInfo<'static> {
+  fn generate(rng: &mut StdRng) -> Self {
+    match rng.gen_range(0..8) {
+      0 => GrappleDeviceInfo::EnumerateRequest,
+      1 => GrappleDeviceInfo::EnumerateResponse {
+        model_id: GrappleModelId::generate(rng),
+        serial: rng.gen(),
+        is_dfu: rng.gen(),
+        is_dfu_in_progress: rng.gen(),
+        version: string(rng),
+        name: string(rng),
+      },
+      2 => GrappleDeviceInfo::Blink { serial: rng.gen() },
+      3 => GrappleDeviceInfo::SetName { serial: rng.gen(), name: string(rng) },
+      4 => GrappleDeviceInfo::CommitConfig { serial: rng.gen() },
+      5 => GrappleDeviceInfo::SetId { serial: rng.gen(), new_id: rng.gen() },
+      6 => GrappleDeviceInfo::ArbitrationRequest,
+      _ => GrappleDeviceInfo::ArbitrationReject,
+    }
+  }
+}
+
+/* NI */
+
+impl Generate for NiDeviceMessage {
+  fn generate(rng: &mut StdRng) -> Self {
+    NiDeviceMessage::RobotController(NiRobotControllerMessage::Heartbeat(NiRioHeartbeat::Hearbeat(NiRioHearbeat1 {
+      reserved1: rng.gen(),
+      reserved2: rng.gen(),
+      reserved3: rng.gen(),
+      reserved4: rng.gen(),
+      reserved5: rng.gen_range(0..8),
+      watchdog_enabled: rng.gen(),
+      test: rng.gen(),
+      autonomous: rng.gen(),
+      enabled: rng.gen(),
+      red_alliance: rng.gen(),
+      reserved6: rng.gen(),
+      reserved7: rng.gen(),
+      reserved8: rng.gen(),
+    })))
+  }
+}
+
+/* HARNESS */
+
+fn for_each_case<F: FnMut(u64, &mut StdRng)>(mut f: F) {
+  for seed in 0..CASES {
+    f(seed, &mut StdRng::seed_from_u64(seed));
+  }
+}
+
+fn assert_roundtrip(seed: u64, msg: Message<'static>) {
+  let mut writer = VecBitWriter::new();
+  msg.write(&mut writer, ()).unwrap_or_else(|e| panic!("seed {}: failed to marshal {:?}: {:?}", seed, msg, e));
+
+  let decoded = Message::read(&mut BitView::new(writer.slice()), ())
+    .unwrap_or_else(|e| panic!("seed {}: failed to demarshal {:?}: {:?}", seed, msg, e));
+  assert_eq!(decoded, msg, "seed {}", seed);
+}
+
+// Sends the message through a fragmenter and both reassemblers, returning the number of frames it took.
+fn assert_fragment_roundtrip(seed: u64, device_id: u8, msg: GrappleDeviceMessage<'static>, format: CanFrameFormat) -> usize {
+  let (mut rx, mut tx) = FragmentReassembler::new_for_format(1000, format).split();
+  let mut fixed_rx = Box::new(FixedFragmentReassemblerRx::<1, 8300>::new(1000));
+
+  let mut frames = vec![];
+  tx.maybe_fragment(device_id, msg.clone(), &mut |id, data| frames.push((id, data.to_vec())))
+    .unwrap_or_else(|e| panic!("seed {}: failed to fragment {:?}: {:?}", seed, msg, e));
+
+  let mut reassembled = None;
+  for (i, (id, data)) in frames.iter().enumerate() {
+    assert!(data.len() <= format.max_data_len(), "seed {}: frame {} is {} bytes", seed, i, data.len());
+    assert!(reassembled.is_none(), "seed {}: message completed before the last fragment", seed);
+
+    let grpl = match ManufacturerMessage::read(&mut BitView::new(&data[..]), *id) {
+      Ok(ManufacturerMessage::Grapple(grpl)) => grpl,
+      other => panic!("seed {}: frame {} decoded as {:?}", seed, i, other),
+    };
+
+    let fixed = fixed_rx.defragment(0, id, grpl.clone())
+      .unwrap_or_else(|e| panic!("seed {}: fixed reassembler failed to reassemble {:?}: {:?}", seed, msg, e))
+      .map(|(gid, m)| (gid, m.to_static()));
+
+    let mut storage = vec![];
+    if let Some((gid, m)) = rx.defragment(0, id, grpl, &mut storage).unwrap_or_else(|e| panic!("seed {}: failed to reassemble {:?}: {:?}", seed, msg, e)) {
+      reassembled = Some((gid, m.to_static()));
+    }
+    assert_eq!(fixed, reassembled, "seed {}: reassemblers disagree", seed);
+  }
+
+  let mut expected_id = GrappleMessageId::new(device_id);
+  binmarshal::MarshalUpdate::update(&mut msg.clone(), &mut expected_id);
+
+  assert_eq!(reassembled, Some((expected_id, msg)), "seed {}", seed);
+  frames.len()
+}
+
+fn check_grapple<F: FnMut(&mut StdRng) -> GrappleDeviceMessage<'static>>(mut generate: F) {
+  let mut fragmented = 0;
+  for_each_case(|seed, rng| {
+    let device_id = rng.gen_range(0..64);
+    let msg = generate(rng);
+
+    assert_roundtrip(seed, Message::new(device_id, ManufacturerMessage::Grapple(MaybeFragment::Message(msg.clone()))));
+    if assert_fragment_roundtrip(seed, device_id, msg.clone(), CanFrameFormat::Classic) > 1 {
+      fragmented += 1;
+    }
+    assert_fragment_roundtrip(seed, device_id, msg, CanFrameFormat::Fd);
+  });
+  assert!(fragmented > 0, "none of the {} cases needed fragmenting", CASES);
+}
+
+#[test]
+fn lasercan() {
+  check_grapple(|rng| GrappleDeviceMessage::DistanceSensor(LaserCanMessage::generate(rng)));
+}
+
+#[test]
+fn mitocandria() {
+  check_grapple(|rng| GrappleDeviceMessage::PowerDistributionModule(MitocandriaMessage::generate(rng)));
+}
+
+#[test]
+fn jms() {
+  check_grapple(|rng| GrappleDeviceMessage::Misc(MiscMessage::JMS(JMSMessage::generate(rng))));
+}
+
+#[test]
+fn misc() {
+  check_grapple(|rng| GrappleDeviceMessage::Misc(MiscMessage::generate(rng)));
+}
+
+#[test]
+fn bridge() {
+  check_grapple(|rng| GrappleDeviceMessage::IOBreakout(FlexiCANMessage::generate(rng)));
+}
+
+#[test]
+fn firmware() {
+  check_grapple(|rng| GrappleDeviceMessage::FirmwareUpdate(GrappleFirmwareMessage::generate(rng)));
+}
+
+#[test]
+fn device_info() {
+  check_grapple(|rng| GrappleDeviceMessage::Broadcast(GrappleBroadcastMessage::DeviceInfo(GrappleDeviceInfo::generate(rng))));
+}
+
+#[test]
+fn large_messages() {
+  for_each_case(|seed, rng| {
+    let device_id = rng.gen_range(0..64);
+    let len = rng.gen_range(256..=8192);
+    let msg = GrappleDeviceMessage::FirmwareUpdate(GrappleFirmwareMessage::UpdatePartV2(Request::Request(UpdatePartV2Payload {
+      offset: rng.gen(),
+      payload: AsymmetricCow(Cow::Owned(PayloadOwned::new((0..len).map(|_| rng.gen()).collect()))),
+    })));
+
+    let frames = assert_fragment_roundtrip(seed, device_id, msg.clone(), CanFrameFormat::Classic);
+    assert!(frames > MAX_STANDARD_FRAGMENTS, "seed {}: only took {} frames", seed, frames);
+    assert_fragment_roundtrip(seed, device_id, msg, CanFrameFormat::Fd);
+  });
+}
+
+#[test]
+fn ni() {
+  for_each_case(|seed, rng| {
+    assert_roundtrip(seed, Message::new(rng.gen_range(0..64), ManufacturerMessage::Ni(NiDeviceMessage::generate(rng))));
+  });
+}
+
+#[test]
+fn bridged_can_message() {
+  for_each_case(|seed, rng| {
+    let msg = BridgedCANMessage {
+      id: message_id(rng),
+      timestamp: rng.gen(),
+      data: AsymmetricCow(Cow::Owned(LengthTaggedPayloadOwned::new(bytes(rng)))),
+    };
+
+    let mut writer = VecBitWriter::new();
+    msg.write(&mut writer, ()).unwrap();
+    assert_eq!(BridgedCANMessage::read(&mut BitView::new(writer.slice()), ()).unwrap(), msg, "seed {}", seed);
+  });
+}
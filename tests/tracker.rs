@@ -0,0 +1,111 @@
+#![cfg(feature = "grapple_lasercan")]
+
+use grapple_frc_msgs::grapple::{
+  errors::GrappleError,
+  lasercan::{LaserCanMessage, LaserCanRangingMode},
+  tracker::{RequestKey, RequestTracker},
+  GrappleDeviceMessage, GrappleMessageId, Request,
+};
+
+fn request(device_id: u8, api_class: u8) -> GrappleMessageId {
+  GrappleMessageId { device_type: 6, fragment_flag: false, ack_flag: false, api_class, api_index: 0, device_id }
+}
+
+fn ack(device_id: u8, api_class: u8) -> GrappleMessageId {
+  GrappleMessageId { ack_flag: true, ..request(device_id, api_class) }
+}
+
+fn expire(tracker: &mut RequestTracker<u32>, now: i64) -> Vec<(RequestKey, u32, GrappleError<'static>)> {
+  let mut expired = vec![];
+  tracker.expire(now, &mut |key, tag, err| expired.push((key, tag, err)));
+  expired
+}
+
+#[test]
+fn resolves_oldest_matching_request() {
+  let mut tracker = RequestTracker::new(100);
+  tracker.track(0, &request(1, 1), 1);
+  tracker.track(0, &request(2, 1), 2);
+  tracker.track(0, &request(1, 2), 3);
+  tracker.track(0, &request(1, 1), 4);
+
+  assert_eq!(tracker.resolve(&ack(1, 1)), Some(1));
+  assert_eq!(tracker.resolve(&ack(1, 2)), Some(3));
+  assert_eq!(tracker.resolve(&ack(1, 1)), Some(4));
+  assert_eq!(tracker.resolve(&ack(1, 1)), None);
+  assert_eq!(tracker.len(), 1);
+  assert_eq!(tracker.resolve(&ack(2, 1)), Some(2));
+  assert!(tracker.is_empty());
+}
+
+#[test]
+fn ignores_non_acks() {
+  let mut tracker = RequestTracker::new(100);
+  tracker.track(0, &request(1, 1), 1);
+
+  // The request itself, echoed back or seen from another host on the bus
+  assert_eq!(tracker.resolve(&request(1, 1)), None);
+  // Acks for something else
+  assert_eq!(tracker.resolve(&ack(2, 1)), None);
+  assert_eq!(tracker.resolve(&ack(1, 2)), None);
+  assert_eq!(tracker.resolve(&GrappleMessageId { device_type: 7, ..ack(1, 1) }), None);
+  assert_eq!(tracker.len(), 1);
+
+  assert_eq!(tracker.resolve(&ack(1, 1)), Some(1));
+}
+
+#[test]
+fn track_message() {
+  let mut tracker = RequestTracker::new(100);
+  let mut msg = GrappleDeviceMessage::DistanceSensor(LaserCanMessage::SetRange(Request::Request(LaserCanRangingMode::Long)));
+  let id = tracker.track_message(0, 3, &mut msg, 1);
+  assert_eq!((id.device_type, id.device_id, id.ack_flag), (6, 3, false));
+
+  assert_eq!(tracker.resolve(&GrappleMessageId { ack_flag: true, ..id }), Some(1));
+}
+
+#[test]
+fn cancel() {
+  let mut tracker = RequestTracker::new(100);
+  for (i, device_id) in [1, 2, 1, 3].into_iter().enumerate() {
+    tracker.track(0, &request(device_id, 1), i as u32);
+  }
+
+  tracker.cancel(|key, _| key.device_id == 1);
+  assert_eq!(tracker.len(), 2);
+  assert_eq!(tracker.resolve(&ack(1, 1)), None);
+
+  tracker.cancel(|_, tag| *tag == 3);
+  assert_eq!(tracker.resolve(&ack(3, 1)), None);
+  assert_eq!(tracker.resolve(&ack(2, 1)), Some(1));
+}
+
+#[test]
+fn expire_at_deadline() {
+  let mut tracker = RequestTracker::new(100);
+  tracker.track(0, &request(1, 1), 1);
+  tracker.track(50, &request(2, 1), 2);
+
+  // Requests are still live at their deadline, and expire just after it
+  assert!(expire(&mut tracker, 100).is_empty());
+  let expired = expire(&mut tracker, 101);
+  assert_eq!(expired.len(), 1);
+  assert_eq!((expired[0].0.device_id, expired[0].1), (1, 1));
+  assert!(matches!(expired[0].2, GrappleError::TimedOut(_)));
+
+  assert!(expire(&mut tracker, 150).is_empty());
+  assert_eq!(expire(&mut tracker, 151).iter().map(|x| x.1).collect::<Vec<_>>(), [2]);
+  assert!(tracker.is_empty());
+}
+
+#[test]
+fn deadline_saturates() {
+  let mut tracker = RequestTracker::new(100);
+  tracker.track(i64::MAX - 10, &request(1, 1), 1);
+  assert!(expire(&mut tracker, i64::MAX).is_empty());
+
+  let mut tracker = RequestTracker::new(i64::MAX);
+  tracker.track(1, &request(1, 1), 1);
+  assert!(expire(&mut tracker, i64::MAX).is_empty());
+  assert_eq!(tracker.resolve(&ack(1, 1)), Some(1));
+}
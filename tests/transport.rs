@@ -0,0 +1,142 @@
+#![cfg(feature = "std")]
+
+use std::borrow::Cow;
+
+use binmarshal::AsymmetricCow;
+use bounded_static::ToBoundedStatic;
+use grapple_frc_msgs::{
+  can::CanFrameFormat,
+  grapple::{misc::MiscMessage, GrappleDeviceMessage},
+  transport::{loopback::LoopbackBus, CanTransport, GrappleTransport, TransportError},
+  MessageId,
+};
+
+fn misc(payload: &[u8]) -> GrappleDeviceMessage<'static> {
+  GrappleDeviceMessage::Misc(MiscMessage::MiscMessage(AsymmetricCow(Cow::Borrowed(payload.into()))).to_static())
+}
+
+fn recv_all(node: &mut impl CanTransport<Error = TransportError>) -> Vec<(MessageId, Vec<u8>)> {
+  let mut frames = vec![];
+  while let Some(frame) = node.recv().unwrap() {
+    frames.push((frame.id, frame.data.to_vec()));
+  }
+  frames
+}
+
+fn id(n: u32) -> MessageId {
+  MessageId::from(0x0C0180C0 | n)
+}
+
+#[test]
+fn loopback_fan_out() {
+  let bus = LoopbackBus::new(CanFrameFormat::Classic);
+  let mut a = bus.node();
+  let mut b = bus.node();
+  let mut c = bus.node();
+
+  a.send(id(1), &[1]).unwrap();
+  b.send(id(2), &[2, 2]).unwrap();
+  a.send(id(3), &[]).unwrap();
+
+  // Every other node sees every frame, in the order it was sent, but never its own
+  assert_eq!(recv_all(&mut a), [(id(2), vec![2, 2])]);
+  assert_eq!(recv_all(&mut b), [(id(1), vec![1]), (id(3), vec![])]);
+  assert_eq!(recv_all(&mut c), [(id(1), vec![1]), (id(2), vec![2, 2]), (id(3), vec![])]);
+  assert_eq!(c.recv(), Ok(None));
+}
+
+#[test]
+fn loopback_echo() {
+  let bus = LoopbackBus::new(CanFrameFormat::Classic);
+  let mut a = bus.node();
+  let mut b = bus.node();
+  a.set_echo(true);
+
+  a.send(id(1), &[1]).unwrap();
+  assert_eq!(recv_all(&mut a), [(id(1), vec![1])]);
+  assert_eq!(recv_all(&mut b), [(id(1), vec![1])]);
+}
+
+#[test]
+fn loopback_dropped_node() {
+  let bus = LoopbackBus::new(CanFrameFormat::Classic);
+  let mut a = bus.node();
+  let b = bus.node();
+  a.send(id(1), &[1]).unwrap();
+  drop(b);
+
+  // The rest of the bus carries on, and a node joining later only sees frames sent after it joined
+  a.send(id(2), &[2]).unwrap();
+  let mut c = bus.node();
+  a.send(id(3), &[3]).unwrap();
+  assert_eq!(recv_all(&mut c), [(id(3), vec![3])]);
+
+  // Dropping the bus handle doesn't disconnect nodes that are still alive
+  drop(bus);
+  c.send(id(4), &[4]).unwrap();
+  assert_eq!(recv_all(&mut a), [(id(4), vec![4])]);
+}
+
+#[test]
+fn loopback_frame_too_long() {
+  for (format, max) in [(CanFrameFormat::Classic, 8), (CanFrameFormat::Fd, 64)] {
+    let bus = LoopbackBus::new(format);
+    let mut a = bus.node();
+    let mut b = bus.node();
+    assert_eq!(a.format(), format);
+
+    a.send(id(1), &vec![0; max]).unwrap();
+    assert_eq!(a.send(id(2), &vec![0; max + 1]), Err(TransportError::FrameTooLong));
+    // Rejected frames never reach the bus
+    assert_eq!(recv_all(&mut b), [(id(1), vec![0; max])]);
+  }
+}
+
+#[test]
+fn transport_fragmented_roundtrip() {
+  for format in [CanFrameFormat::Classic, CanFrameFormat::Fd] {
+    let bus = LoopbackBus::new(format);
+    let mut sender = GrappleTransport::new(bus.node(), 1_000_000);
+    let mut receiver = GrappleTransport::new(bus.node(), 1_000_000);
+    let mut sniffer = bus.node();
+
+    // Both fragment formats, then a single frame
+    sender.send(3, misc(&[0x55; 100])).unwrap();
+    sender.send(3, misc(&[0xAA; 300])).unwrap();
+    sender.send(3, misc(&[1, 2])).unwrap();
+    assert!(recv_all(&mut sniffer).len() > 3);
+
+    let (id, msg) = receiver.recv().unwrap().unwrap();
+    assert_eq!((id.device_id, msg), (3, misc(&[0x55; 100])));
+    assert_eq!(receiver.recv().unwrap().map(|(_, msg)| msg), Some(misc(&[0xAA; 300])));
+    assert_eq!(receiver.recv().unwrap().map(|(_, msg)| msg), Some(misc(&[1, 2])));
+    assert_eq!(receiver.recv(), Ok(None));
+    assert_eq!(receiver.rx().stats().completed, 2);
+  }
+}
+
+#[test]
+fn transport_interleaved_senders() {
+  let bus = LoopbackBus::new(CanFrameFormat::Classic);
+  let mut receiver = GrappleTransport::new(bus.node(), 1_000_000);
+  let mut raw = bus.node();
+
+  // Three devices sending at once, with their fragments interleaved on the bus and other
+  // manufacturers' frames in between
+  let mut senders: Vec<_> = (1..=3).map(|_| GrappleTransport::new(bus.node(), 1_000_000)).collect();
+  let transfers: Vec<Vec<_>> = senders.iter_mut().enumerate()
+    .map(|(i, s)| s.tx_mut().fragment(i as u8 + 1, misc(&[i as u8; 30])).unwrap().collect())
+    .collect();
+  for n in 0..transfers[0].len() {
+    for frames in &transfers {
+      raw.send(frames[n].0, &frames[n].1).unwrap();
+    }
+    raw.send(MessageId::from(0x0205_0000), &[0xFF; 8]).unwrap();
+  }
+
+  let mut received = vec![];
+  while let Some((id, msg)) = receiver.recv().unwrap() {
+    received.push((id.device_id, msg));
+  }
+  assert_eq!(received, [(1, misc(&[0; 30])), (2, misc(&[1; 30])), (3, misc(&[2; 30]))]);
+}
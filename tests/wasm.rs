@@ -0,0 +1,83 @@
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use grapple_frc_msgs::wasm::{self, DeviceEnumerator, Frame, WasmFragmentReassembler as FragmentReassembler};
+use serde_json::{json, Value};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn to_json(value: wasm_bindgen::JsValue) -> Value {
+  serde_wasm_bindgen::from_value(value).unwrap()
+}
+
+fn from_json(value: &Value) -> wasm_bindgen::JsValue {
+  use serde::Serialize;
+  value.serialize(&serde_wasm_bindgen::Serializer::json_compatible()).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn decode_encode_roundtrip() {
+  let decoded = to_json(wasm::decode(0x06060803, &[0x77, 0xFF]).unwrap());
+  assert_eq!(decoded["id"]["device_id"], 3);
+
+  let frame = wasm::encode(from_json(&decoded)).unwrap();
+  assert_eq!(frame, Frame::new(0x06060803, vec![0x77, 0xFF]));
+}
+
+#[wasm_bindgen_test]
+fn fragment_roundtrip() {
+  let msg = json!({
+    "id": { "device_type": 0, "manufacturer": 6, "api_class": 0, "api_index": 3, "device_id": 4 },
+    "msg": { "Grapple": { "type": "Message", "data": { "type": "Broadcast", "data": {
+      "type": "DeviceInfo", "data": { "type": "SetName", "data": { "serial": 1234, "name": "A much longer device name" } }
+    } } } }
+  });
+
+  let mut reassembler = FragmentReassembler::new(1000, 8);
+  let frames = reassembler.fragment(from_json(&msg)).unwrap();
+  assert!(frames.len() > 1);
+
+  let mut out = None;
+  for frame in frames {
+    let result = reassembler.defragment(0, frame.id, &frame.data()).unwrap();
+    if !result.is_undefined() {
+      out = Some(to_json(result));
+    }
+  }
+  assert_eq!(out.unwrap()["msg"], msg["msg"]);
+}
+
+#[wasm_bindgen_test]
+fn enumerate() {
+  let mut enumerator = DeviceEnumerator::new(1000);
+  let request = enumerator.request().unwrap();
+  assert_eq!(request.len(), 1);
+  assert_eq!(request[0].id & 0x3F, 0x3F);
+
+  let response = json!({
+    "id": { "device_type": 0, "manufacturer": 6, "api_class": 0, "api_index": 1, "device_id": 7 },
+    "msg": { "Grapple": { "type": "Message", "data": { "type": "Broadcast", "data": {
+      "type": "DeviceInfo", "data": { "type": "EnumerateResponse", "data": {
+        "model_id": "LaserCan", "serial": 42, "is_dfu": false, "is_dfu_in_progress": false, "version": "2025.0.0", "name": "Intake"
+      } }
+    } } } }
+  });
+
+  let mut tx = FragmentReassembler::new(1000, 8);
+  for frame in tx.fragment(from_json(&response)).unwrap() {
+    enumerator.handle(10, frame.id, &frame.data()).unwrap();
+  }
+
+  let devices = to_json(enumerator.devices().unwrap());
+  assert_eq!(devices, json!([{
+    "device_id": 7, "model_id": "LaserCan", "serial": 42, "is_dfu": false, "is_dfu_in_progress": false,
+    "version": "2025.0.0", "name": "Intake", "last_seen": 10
+  }]));
+
+  enumerator.prune(2000, 1000);
+  assert_eq!(to_json(enumerator.devices().unwrap()), json!([]));
+}
+
+#[wasm_bindgen_test]
+fn schema() {
+  let schema = to_json(wasm::schema().unwrap());
+  assert_eq!(schema["title"], "Message");
+}